@@ -0,0 +1,224 @@
+use anyhow::{bail, Result};
+use image::{DynamicImage, RgbImage, RgbaImage};
+use re_rav1d::pixel::{MatrixCoefficients, YUVRange};
+use re_rav1d::{Decoder, Error, Picture, PixelLayout, PlanarImageComponent, Plane, Settings};
+
+/// 解码 AVIF 图片的主图像，带有 alpha 图像时合成为 RGBA，高位深统一降为 8 位
+pub fn decode(content: &[u8]) -> Result<DynamicImage> {
+    let avif = avif_parse::read_avif(&mut &content[..])?;
+    let picture = decode_av1(&avif.primary_item)?;
+    let (width, height) = (picture.width(), picture.height());
+    let rgb = to_rgb(&picture);
+    let Some(alpha) = &avif.alpha_item else {
+        return match RgbImage::from_raw(width, height, rgb) {
+            Some(image) => Ok(image.into()),
+            None => bail!("AVIF 图像数据不完整"),
+        };
+    };
+    let alpha = decode_av1(alpha)?;
+    if (alpha.width(), alpha.height()) != (width, height) {
+        bail!("AVIF 的 alpha 图像尺寸与主图像不一致");
+    }
+    let samples = Samples::new(&alpha, PlanarImageComponent::Y);
+    let range = Range::new(&alpha);
+    let mut rgba = Vec::with_capacity(rgb.len() / 3 * 4);
+    for (index, pixel) in rgb.chunks_exact(3).enumerate() {
+        let (x, y) = (index % width as usize, index / width as usize);
+        let a = to_u8(range.luma(samples.get(x, y)));
+        rgba.extend(pixel.iter().map(|&c| match avif.premultiplied_alpha {
+            true if a > 0 => (c as u32 * 255 / a as u32).min(255) as u8,
+            _ => c,
+        }));
+        rgba.push(a);
+    }
+    match RgbaImage::from_raw(width, height, rgba) {
+        Some(image) => Ok(image.into()),
+        None => bail!("AVIF 图像数据不完整"),
+    }
+}
+
+/// 解码一帧 AV1 图像，AVIF 的主图像与 alpha 图像各自是一帧
+fn decode_av1(data: &[u8]) -> Result<Picture> {
+    let mut settings = Settings::new();
+    settings.set_n_threads(1);
+    settings.set_max_frame_delay(1);
+    let mut decoder = Decoder::with_settings(&settings)?;
+    let mut pending = is_pending(decoder.send_data(data.to_vec(), None, None, None))?;
+    loop {
+        match decoder.get_picture() {
+            Ok(picture) => return Ok(picture),
+            Err(Error::Again) if pending => pending = is_pending(decoder.send_pending_data())?,
+            Err(Error::Again) => bail!("AVIF 中没有可解码的图像"),
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// 数据是否还有未送入解码器的部分
+fn is_pending(result: Result<(), Error>) -> Result<bool> {
+    match result {
+        Ok(()) => Ok(false),
+        Err(Error::Again) => Ok(true),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// 按矩阵系数与取值范围将 YUV 平面转换为交错排列的 8 位 RGB
+fn to_rgb(picture: &Picture) -> Vec<u8> {
+    let (width, height) = (picture.width() as usize, picture.height() as usize);
+    let layout = picture.pixel_layout();
+    let (shift_x, shift_y) = match layout {
+        PixelLayout::I420 => (1, 1),
+        PixelLayout::I422 => (1, 0),
+        PixelLayout::I400 | PixelLayout::I444 => (0, 0),
+    };
+    let luma = Samples::new(picture, PlanarImageComponent::Y);
+    let chroma = (layout != PixelLayout::I400).then(|| {
+        (
+            Samples::new(picture, PlanarImageComponent::U),
+            Samples::new(picture, PlanarImageComponent::V),
+        )
+    });
+    let range = Range::new(picture);
+    let matrix = picture.matrix_coefficients();
+    let (kr, kb) = match matrix {
+        MatrixCoefficients::BT709 => (0.2126, 0.0722),
+        MatrixCoefficients::BT2020NonConstantLuminance
+        | MatrixCoefficients::BT2020ConstantLuminance => (0.2627, 0.0593),
+        _ => (0.299, 0.114),
+    };
+    let mut rgb = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let l = luma.get(x, y);
+            let pixel = match &chroma {
+                None => [range.luma(l); 3],
+                Some((u, v)) => {
+                    let (u, v) = (
+                        u.get(x >> shift_x, y >> shift_y),
+                        v.get(x >> shift_x, y >> shift_y),
+                    );
+                    match matrix {
+                        // GBR 直接存放在 Y、U、V 三个平面
+                        MatrixCoefficients::Identity => {
+                            [range.luma(v), range.luma(l), range.luma(u)]
+                        }
+                        _ => {
+                            let (l, u, v) = (range.luma(l), range.chroma(u), range.chroma(v));
+                            let r = l + 2.0 * (1.0 - kr) * v;
+                            let b = l + 2.0 * (1.0 - kb) * u;
+                            let g = (l - kr * r - kb * b) / (1.0 - kr - kb);
+                            [r, g, b]
+                        }
+                    }
+                }
+            };
+            rgb.extend(pixel.map(to_u8));
+        }
+    }
+    rgb
+}
+
+fn to_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// 一个平面的采样值，位深大于 8 时每个采样占两个字节
+struct Samples {
+    plane: Plane,
+    stride: usize,
+    wide: bool,
+}
+
+impl Samples {
+    fn new(picture: &Picture, component: PlanarImageComponent) -> Self {
+        Samples {
+            plane: picture.plane(component),
+            stride: picture.stride(component) as usize,
+            wide: picture.bit_depth() > 8,
+        }
+    }
+
+    fn get(&self, x: usize, y: usize) -> f32 {
+        let row = &self.plane[y * self.stride..];
+        match self.wide {
+            true => u16::from_ne_bytes([row[x * 2], row[x * 2 + 1]]) as f32,
+            false => row[x] as f32,
+        }
+    }
+}
+
+/// 将采样值归一化，亮度为 0 到 1，色度为 -0.5 到 0.5
+struct Range {
+    full: bool,
+    max: f32,
+    scale: f32,
+}
+
+impl Range {
+    fn new(picture: &Picture) -> Self {
+        let depth = picture.bit_depth() as i32;
+        Range {
+            full: picture.color_range() == YUVRange::Full,
+            max: (2f32.powi(depth)) - 1.0,
+            scale: 2f32.powi(depth - 8),
+        }
+    }
+
+    fn luma(&self, value: f32) -> f32 {
+        match self.full {
+            true => value / self.max,
+            false => (value - 16.0 * self.scale) / (219.0 * self.scale),
+        }
+    }
+
+    fn chroma(&self, value: f32) -> f32 {
+        match self.full {
+            true => (value - (self.max + 1.0) / 2.0) / self.max,
+            false => (value - 128.0 * self.scale) / (224.0 * self.scale),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() -> Result<()> {
+        let image = decode(&std::fs::read("tests/images/sample.avif")?)?;
+        assert_eq!((image.width(), image.height()), (16, 12));
+        assert!(!image.color().has_alpha());
+        // 样例由 (x * 16, y * 20, 128) 的渐变有损压缩而来
+        for (x, y, pixel) in image.to_rgb8().enumerate_pixels() {
+            let expected = [x * 16, y * 20, 128];
+            for (actual, expected) in pixel.0.iter().zip(expected) {
+                assert!(
+                    (*actual as i32 - expected as i32).abs() <= 24,
+                    "({}, {}) {:?}",
+                    x,
+                    y,
+                    pixel
+                );
+            }
+        }
+
+        // 左半透明，不透明部分为 (200, x * 16, y * 20)
+        let image = decode(&std::fs::read("tests/images/sample_alpha.avif")?)?;
+        assert!(image.color().has_alpha());
+        for (x, y, pixel) in image.to_rgba8().enumerate_pixels() {
+            let [r, g, b, a] = pixel.0;
+            match x < 8 {
+                true => assert!(a < 8, "({}, {}) {:?}", x, y, pixel),
+                false => {
+                    assert!(a > 247, "({}, {}) {:?}", x, y, pixel);
+                    for (actual, expected) in [r, g, b].into_iter().zip([200, x * 16, y * 20]) {
+                        assert!((actual as i32 - expected as i32).abs() <= 24);
+                    }
+                }
+            }
+        }
+        assert!(decode(b"\x00\x00\x00\x1cftypavif\x00").is_err());
+        Ok(())
+    }
+}
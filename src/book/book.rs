@@ -1,112 +1,155 @@
+use super::epub::{self, Entry, EpubWriter};
+use super::image::{self, with_extension, ImageFormat, SizeReport};
+use super::manifest::{hash, ChapterRecord};
+use super::ChapterSelection;
 use super::Opf;
-use super::{toc::Ncx, Episode};
-use crate::config::TEMPLATE;
+use super::{
+    image_sources, safe_file_name, sanitize, toc::Ncx, xhtml, Cache, Episode, Failure,
+    FailureReport, Manifest, Nav,
+};
+use super::{OutputFiles, OutputTemplate};
+use crate::config::{DeviceProfile, OutputFormat};
+use crate::library::{Library, LibraryEntry};
+use crate::source::{BookInfo, Source};
 use crate::CONFIG;
-use crate::{Credential, Downloader};
-use anyhow::Context;
 use anyhow::Result;
 use md5::{Digest, Md5};
-use reqwest::Method;
-use scraper::selectable::Selectable;
-use scraper::{Html, Selector};
-use std::io::{Read, Seek, Write};
+use quick_xml::escape::escape;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::BufWriter;
+use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::vec;
-use std::{collections::HashMap, path::Path};
-use tracing::{debug, error, info};
-use walkdir::{DirEntry, WalkDir};
-use zip::{result::ZipError, write::SimpleFileOptions};
+use tracing::{debug, info, warn};
+/// 一次下载的选项
+#[derive(Default, Clone)]
+pub struct DownloadOptions {
+    /// 只下载新增或标题变化的章节以及缺失的插画
+    pub update: bool,
+    /// 只下载选中的章节
+    pub selection: ChapterSelection,
+}
+
+/// 分卷输出时所属的系列
+#[derive(Clone, Debug)]
+pub struct Series {
+    pub name: String,
+    pub index: u32,
+}
+
 pub struct Book {
+    pub url: String,
     pub title: String,
     pub author: String,
+    /// 站点名称
+    pub source: String,
+    /// 站点的显示名称，写入 dc:publisher
+    pub publisher: String,
+    /// 站点显示的最后更新日期
+    pub updated: Option<String>,
+    /// 详情页中的类型、标签、字数与简介
+    pub info: BookInfo,
     pub episodes: Vec<Episode>,
     pub save_path: PathBuf,
     pub illustration_urls: HashMap<String, String>,
-    pub with_cover: bool,
+    /// 用作封面的插画文件名，为空时使用默认封面
+    pub cover: Option<String>,
+    /// 远端目录中的全部卷名，按出现顺序排列
+    pub volumes: Vec<String>,
+    /// 远端目录中全部章节的地址，按目录顺序排列
+    pub chapter_urls: Vec<String>,
+    pub series: Option<Series>,
+    pub format: OutputFormat,
+    /// 打包前按此缩放并重新压缩图片，为空时保留原图
+    pub device_profile: Option<DeviceProfile>,
+    /// 在封面后加入「关于本书」页面
+    pub about_page: bool,
+    /// 本次下载失败的章节与插画
+    pub failures: FailureReport,
+    /// 只使用小说目录中的缓存，不访问网络
+    pub offline: bool,
+    /// 小说目录与书库所在的目录
+    pub root_path: PathBuf,
+    /// epub 的输出目录
+    pub output_path: PathBuf,
 }
-// TODO: 引入信号量控制并发数
 impl Book {
     pub fn new() -> Self {
         // let save_path = Path::new(&CONFIG.esj_zone_config.esj_root_path).join(&title);
         Book {
+            url: String::new(),
             title: String::new(),
             author: String::new(),
+            source: String::new(),
+            publisher: String::new(),
+            updated: None,
+            info: BookInfo::default(),
             episodes: vec![],
             save_path: PathBuf::new(),
             illustration_urls: HashMap::new(),
-            with_cover: false,
+            cover: None,
+            volumes: vec![],
+            chapter_urls: vec![],
+            series: None,
+            format: CONFIG.epub_config.format,
+            device_profile: CONFIG.epub_config.device_profile().cloned(),
+            about_page: CONFIG.epub_config.about_page,
+            failures: FailureReport::default(),
+            offline: CONFIG.cache_config.offline,
+            root_path: PathBuf::from(&CONFIG.esj_zone_config.esj_root_path),
+            output_path: PathBuf::from(&CONFIG.esj_zone_config.esj_output_path),
         }
     }
 
-    async fn init_dir(&self) -> Result<()> {
-        if let Err(_) = tokio::fs::metadata(&CONFIG.esj_zone_config.esj_root_path).await {
+    /// 由来源地址生成的固定 UUID，重新下载或更新后保持不变，分卷时各卷不同
+    pub fn identifier(&self) -> String {
+        let name = match &self.series {
+            Some(series) => format!("{}#{}", self.url, series.index),
+            None => self.url.clone(),
+        };
+        let uuid = uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_URL, name.as_bytes());
+        format!("urn:uuid:{}", uuid)
+    }
+
+    /// 初始化小说目录，增量更新时保留 `manifest.json`、章节记录与已下载的插画，
+    /// 重新下载时只保留下载缓存与本次获取的章节
+    async fn init_dir(&self, update: bool) -> Result<()> {
+        if tokio::fs::metadata(&self.root_path).await.is_err() {
             info!("创建小说生成目录");
-            tokio::fs::create_dir(&CONFIG.esj_zone_config.esj_root_path).await?;
+            tokio::fs::create_dir_all(&self.root_path).await?;
         }
-        if let Err(_) = tokio::fs::metadata(&CONFIG.esj_zone_config.esj_output_path).await {
-            info!("创建输出目录");
-            tokio::fs::create_dir(&CONFIG.esj_zone_config.esj_output_path).await?;
-        }
-        if let Ok(_) = tokio::fs::metadata(&self.save_path).await {
+        if update {
+            // 旧版本将插画下载到打包用的目录中，迁移后删除旧的打包目录
+            let legacy_image_path = self.save_path.join("OEBPS").join("Images");
+            if legacy_image_path.is_dir() && !self.image_path().exists() {
+                tokio::fs::rename(&legacy_image_path, self.image_path()).await?;
+            }
+            for legacy in ["mimetype", "META-INF", "OEBPS", "volumes"] {
+                let legacy = self.save_path.join(legacy);
+                match legacy.is_dir() {
+                    true => tokio::fs::remove_dir_all(legacy).await?,
+                    false if legacy.is_file() => tokio::fs::remove_file(legacy).await?,
+                    false => {}
+                }
+            }
+        } else if tokio::fs::metadata(&self.save_path).await.is_ok() {
             info!("《{}》目录已存在，删除", self.title);
-            tokio::fs::remove_dir_all(&self.save_path).await?;
-        }
-        tokio::fs::create_dir(&self.save_path).await?;
-        let meta_inf_path = Path::new(&self.save_path).join("META-INF");
-        let oebps_path = Path::new(&self.save_path).join("OEBPS");
-        let mimetype_path = Path::new(&self.save_path).join("mimetype");
-        let create_meta_inf_path_task =
-            tokio::spawn(async move { tokio::fs::create_dir(meta_inf_path).await });
-        let create_oebps_path_task =
-            tokio::spawn(async move { tokio::fs::create_dir(oebps_path).await });
-        let create_mimetype_task = tokio::spawn(async move {
-            let _ = tokio::fs::write(mimetype_path, "application/epub+zip".as_bytes()).await;
-        });
-        let _ = tokio::join!(
-            create_meta_inf_path_task,
-            create_oebps_path_task,
-            create_mimetype_task
-        );
-
-        let container_path = Path::new(&self.save_path)
-            .join("META-INF")
-            .join("container.xml");
-        let opf: String = Opf::new(&self).content()?;
-        let opf_path = Path::new(&self.save_path).join("OEBPS").join("content.opf");
-        let toc = Ncx::new(&self.title, &self.author, &self.episodes).content()?;
-        let toc_path = Path::new(&self.save_path).join("OEBPS").join("toc.ncx");
-        let fonts_path = Path::new(&self.save_path).join("OEBPS").join("FONTS");
-        let image_path = Path::new(&self.save_path).join("OEBPS").join("Images");
-        let style_path = Path::new(&self.save_path).join("OEBPS").join("STYLES");
-        let text_path = Path::new(&self.save_path).join("OEBPS").join("Text");
-
-        let create_mimetype_task = tokio::spawn(async move {
-            tokio::fs::copy("./template/container.xml", container_path).await
-        });
-
-        let create_toc_task = tokio::spawn(async move { tokio::fs::write(toc_path, toc).await });
-
-        let create_opf_task = tokio::spawn(async move { tokio::fs::write(opf_path, opf).await });
-
-        let create_fonts_path_task =
-            tokio::spawn(async move { tokio::fs::create_dir(fonts_path).await });
-        let create_images_path_task =
-            tokio::spawn(async move { tokio::fs::create_dir(image_path).await });
-        let create_style_path_task =
-            tokio::spawn(async move { tokio::fs::create_dir(style_path).await });
-        let create_text_path_task =
-            tokio::spawn(async move { tokio::fs::create_dir(text_path).await });
-
-        let _ = tokio::join!(
-            create_mimetype_task,
-            create_toc_task,
-            create_opf_task,
-            create_fonts_path_task,
-            create_images_path_task,
-            create_style_path_task,
-            create_text_path_task
-        );
+            let mut entries = tokio::fs::read_dir(&self.save_path).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name();
+                // 保留章节与上次的记录，未选中或获取失败的章节仍可沿用
+                let kept = [Cache::DIR_NAME, Manifest::CHAPTER_DIR, Manifest::FILE_NAME];
+                match kept.iter().any(|kept| name == *kept) {
+                    true => continue,
+                    false if entry.file_type().await?.is_dir() => {
+                        tokio::fs::remove_dir_all(entry.path()).await?
+                    }
+                    false => tokio::fs::remove_file(entry.path()).await?,
+                }
+            }
+        }
+        tokio::fs::create_dir_all(self.image_path()).await?;
         info!(
             "《{}》初始化完成， 路径为: {}",
             self.title,
@@ -115,260 +158,883 @@ impl Book {
         Ok(())
     }
 
-    async fn save_episodes(&self) -> Result<()> {
-        info!("开始保存小说《{}》章节", self.title);
-        let title_page_path = Path::new(&self.save_path)
-            .join("OEBPS")
-            .join("Text")
-            .join("titlepage.xhtml");
+    /// 已下载插画的保存目录
+    fn image_path(&self) -> PathBuf {
+        self.save_path.join("images")
+    }
 
-        let create_episode_tasks: Vec<_> = self
-            .episodes
+    /// 生成章节页面，并将插画地址替换为 epub 内的路径
+    fn episode_page(&self, episode: &Episode) -> String {
+        let images = self
+            .illustration_urls
             .iter()
-            .map(|episode| {
-                debug!("开始保存《{}》- {}", self.title, episode.episode_title);
-                let episode_path = Path::new(&self.save_path)
-                    .join("OEBPS")
-                    .join("Text")
-                    .join(format!("{}{}", episode.order, ".xhtml"));
-
-                let content = self.illustration_urls.iter().fold(
-                    episode.episode(),
-                    |acc, (url, illustration_name)| {
-                        acc.replace(url, &format!("../Images/{}", illustration_name))
-                    },
-                );
-                tokio::fs::write(episode_path, content)
-            })
+            .map(|(url, name)| (url.clone(), format!("../Images/{}", name)))
             .collect();
+        let content = sanitize(&episode.content, &images);
+        Episode {
+            content,
+            ..episode.clone()
+        }
+        .episode(self.format)
+    }
 
-        let create_title_page_task = tokio::spawn(async move {
-            tokio::fs::copy("./template/titlepage.xhtml", title_page_path).await
-        });
-        let _ = create_title_page_task.await?;
-        for create_episode_task in create_episode_tasks {
-            create_episode_task.await?;
+    /// 「关于本书」页面的正文，列出作者、类型、连载状态、标签等信息与简介，没有的项不显示
+    fn about_page_body(&self) -> String {
+        let mut body = format!("<h1>{}</h1>\n", escape(self.title.as_str()));
+        let tags = self.info.tags.join("、");
+        let word_count = self.info.word_count.map(|count| count.to_string());
+        let fields = [
+            ("作者", Some(self.author.as_str())),
+            ("类型", self.info.kind.as_deref()),
+            ("状态", self.info.status.as_deref()),
+            ("标签", Some(tags.as_str())),
+            ("字数", word_count.as_deref()),
+            ("最后更新", self.updated.as_deref()),
+        ];
+        for (name, value) in fields {
+            if let Some(value) = value.filter(|value| !value.is_empty()) {
+                body += &format!("  <p>{}：{}</p>\n", name, escape(value));
+            }
         }
-        info!("《{}》全章节保存完毕", self.title);
-        Ok(())
+        if !self.url.is_empty() {
+            let url = escape(self.url.as_str());
+            body += &format!("  <p>来源：<a href=\"{}\">{}</a></p>\n", url, url);
+        }
+        if let Some(synopsis) = &self.info.synopsis {
+            body += "  <h2>简介</h2>\n";
+            for line in synopsis.lines() {
+                body += &format!("  <p>{}</p>\n", escape(line));
+            }
+        }
+        body
     }
 
-    fn update_illustration_urls(&mut self) {
-        let content_selector = Selector::parse(r#"div[class="forum-content mt-3"]"#)
-            .expect("Failed to parse content selector");
-        let img_selector = Selector::parse("img").expect("Failed to parse image selector");
-        self.episodes.iter().for_each(|episode| {
-            let doc = Html::parse_document(&episode.content);
-            doc.select(&content_selector)
-                .flat_map(|content| content.select(&img_selector))
-                .for_each(|img| {
-                    if let Some(illustration_url) = img.value().attr("src") {
-                        let res = {
-                            let mut hasher = Md5::new();
-                            hasher.update(illustration_url);
-                            hex::encode(hasher.finalize()) + ".jpg"
-                        };
-                        self.illustration_urls
-                            .entry(illustration_url.to_string())
-                            .or_insert(res);
-                    }
-                });
-        })
+    /// 封面图片，封面未下载成功时使用默认封面
+    fn cover_path(&self) -> PathBuf {
+        self.cover
+            .as_ref()
+            .map(|name| self.image_path().join(name))
+            .filter(|path| is_non_empty_file(path))
+            .unwrap_or_else(|| PathBuf::from("./template/default_cover.jpg"))
     }
 
-    async fn fetch_book(&mut self, url: &str, credential: Option<&Credential>) -> Result<()> {
-        let body = Downloader::new()
-            .fetch_esj(Method::GET, url, credential)
-            .await?
-            .text()
-            .await?;
+    /// epub 中封面的文件名，扩展名与封面图片的实际格式一致
+    pub fn cover_name(&self) -> String {
+        let format = ImageFormat::sniff_file(&self.cover_path())
+            .ok()
+            .flatten()
+            .unwrap_or(ImageFormat::Jpeg);
+        with_extension("cover", format)
+    }
 
-        let doc = Html::parse_document(&body);
-        let title_selector = Selector::parse(r#"h2[class="p-t-10 text-normal"]"#)
-            .expect("Failed to parse title selector");
-        let author_selector = Selector::parse(r#"ul[class="list-unstyled mb-2 book-detail"]"#)
-            .expect("Failed to parse title selector");
-        let episode_list_selector = Selector::parse(r#"div[id="chapterList"]"#)
-            .expect("Failed to parse episode list selector");
-        let a_selector = Selector::parse("a").expect("Failed to parse a tag selector");
-        let cover_selector = Selector::parse(r#"div[class="product-gallery text-center mb-3"]"#)
-            .expect("Failed to parse cover selector");
-
-        if let Some(title) = doc.select(&title_selector).next() {
-            self.title = title.text().collect::<String>();
-        }
-
-        if let Some(cover_url) = doc
-            .select(&cover_selector)
-            .flat_map(|a_elem| a_elem.select(&a_selector))
-            .next()
-        {
-            let cover_url = cover_url
-                .value()
-                .attr("href")
-                .expect("no href tag")
-                .to_string();
-            self.illustration_urls
-                .entry(cover_url)
-                .or_insert("cover.jpg".to_string());
-            self.with_cover = true;
+    /// 按设备配置处理图片，未配置或无需处理时直接读取已下载的文件
+    fn image_entry(&self, path: &str, source: PathBuf, report: &mut SizeReport) -> Result<Entry> {
+        let Some(profile) = &self.device_profile else {
+            return Ok(Entry::file(path, source));
+        };
+        let content = std::fs::read(&source)?;
+        let original = content.len() as u64;
+        match image::fit_profile(&content, profile) {
+            Some(fitted) => {
+                report.add(original, fitted.len() as u64);
+                Ok(Entry::data(path, fitted))
+            }
+            None => {
+                report.add(original, original);
+                Ok(Entry::file(path, source))
+            }
         }
+    }
 
-        if let Some(author) = doc
-            .select(&author_selector)
-            .flat_map(|ul_elem| ul_elem.select(&a_selector))
-            .next()
+    /// epub 中除 `mimetype` 外的全部文件，文本在内存中生成，图片读取已下载的文件并按设备配置处理
+    fn entries(&self, report: &mut SizeReport) -> Result<Vec<Entry>> {
+        let mut entries = vec![
+            Entry::file("META-INF/container.xml", "./template/container.xml"),
+            Entry::data("OEBPS/content.opf", Opf::new(self).content()?),
+            Entry::data(
+                "OEBPS/toc.ncx",
+                Ncx::new(
+                    &self.identifier(),
+                    &self.title,
+                    &self.author,
+                    &self.episodes,
+                    &self.volumes,
+                    self.about_page,
+                )
+                .content()?,
+            ),
+        ];
+        if self.format == OutputFormat::Epub3 {
+            entries.push(Entry::data(
+                "OEBPS/nav.xhtml",
+                Nav::new(&self.title, &self.episodes, self.about_page).content()?,
+            ));
+        }
+        let cover_name = self.cover_name();
+        entries.push(Entry::data(
+            "OEBPS/Text/titlepage.xhtml",
+            xhtml(
+                self.format,
+                "封面",
+                &format!(
+                    r#"<div><img src="../Images/{}" alt="封面"/></div>"#,
+                    cover_name
+                ),
+            ),
+        ));
+        if self.about_page {
+            entries.push(Entry::data(
+                "OEBPS/Text/about.xhtml",
+                xhtml(self.format, "关于本书", &self.about_page_body()),
+            ));
+        }
+        for episode in &self.episodes {
+            debug!("开始写入《{}》- {}", self.title, episode.episode_title);
+            entries.push(Entry::data(
+                &format!("OEBPS/{}", episode.episode_save_path),
+                self.episode_page(episode),
+            ));
+        }
+        entries.push(self.image_entry(
+            &format!("OEBPS/Images/{}", cover_name),
+            self.cover_path(),
+            report,
+        )?);
+        for name in self
+            .illustration_files()
+            .into_iter()
+            .filter(|name| !is_cover(name))
         {
-            self.author = author.text().collect::<String>();
+            entries.push(self.image_entry(
+                &format!("OEBPS/Images/{}", name),
+                self.image_path().join(name),
+                report,
+            )?);
         }
+        Ok(entries)
+    }
 
-        self.save_path = Path::new(&CONFIG.esj_zone_config.esj_root_path).join(&self.title);
-        let episode_list: Vec<String> = doc
-            .select(&episode_list_selector)
-            .flat_map(|div_elem| div_elem.select(&a_selector))
-            .filter_map(|a_elem| a_elem.value().attr("href").map(|url| url.to_string()))
+    /// 解析章节中的插画，新插画先以 `.jpg` 命名，下载后按实际格式修正；
+    /// 不再被章节引用的插画从记录中移除
+    fn update_illustration_urls(&mut self, source: &Arc<dyn Source>) {
+        let referenced: HashSet<String> = self
+            .episodes
+            .iter()
+            .flat_map(|episode| source.resolve_illustrations(&episode.content))
             .collect();
+        self.illustration_urls
+            .retain(|url, name| referenced.contains(url) || self.cover.as_ref() == Some(name));
+        for illustration_url in referenced {
+            let res = hash(&illustration_url) + ".jpg";
+            self.illustration_urls
+                .entry(illustration_url)
+                .or_insert(res);
+        }
+    }
 
-        let esj_credential = Arc::new(Credential {
-            esj_key: CONFIG.esj_zone_config.ews_key.clone(),
-            esj_token: CONFIG.esj_zone_config.ews_token.clone(),
+    /// 获取小说详情与全部章节，增量更新时目录中标题未变的章节直接读取本地记录，
+    /// 上次下载中断时沿用中断前已获取的章节
+    async fn fetch_book(
+        &mut self,
+        source: &Arc<dyn Source>,
+        url: &str,
+        options: &DownloadOptions,
+    ) -> Result<()> {
+        let update = options.update;
+        let page = Cache::fetch_book_page(source, url, &self.root_path, self.offline).await?;
+        let meta = source.parse_book_page(&page)?;
+        self.url = url.to_string();
+        self.title = meta.title;
+        self.author = meta.author;
+        self.source = source.name().to_string();
+        self.publisher = source.display_name().to_string();
+        self.updated = meta.updated;
+        self.info = meta.info;
+        self.save_path = self.root_path.join(safe_file_name(&self.title));
+        let cache = Cache::new(&self.save_path, self.offline).resume().await;
+        cache.save_book_page(url, &page).await;
+        let manifest = match update {
+            true => Manifest::load(&self.save_path).await.unwrap_or_else(|err| {
+                warn!(
+                    "《{}》本地记录读取失败，将重新下载全部章节: {}",
+                    self.title, err
+                );
+                Manifest::default()
+            }),
+            false => Manifest::default(),
+        };
+        // 沿用已下载插画的文件名，避免扩展名修正后重复下载
+        self.illustration_urls = manifest.illustrations.clone();
+        if let Some(cover_url) = meta.cover_url {
+            self.illustration_urls
+                .retain(|url, name| !is_cover(name) || *url == cover_url);
+            let name = self
+                .illustration_urls
+                .entry(cover_url)
+                .or_insert("cover.jpg".to_string());
+            self.cover = Some(name.clone());
+        }
+        self.volumes = meta.chapters.iter().fold(vec![], |mut volumes, chapter| {
+            if let Some(volume) = &chapter.volume {
+                if !volumes.contains(volume) {
+                    volumes.push(volume.clone());
+                }
+            }
+            volumes
         });
-        let fetch_esj_episode_tasks: Vec<_> = episode_list
+        self.chapter_urls = meta
+            .chapters
+            .iter()
+            .map(|chapter| chapter.url.clone())
+            .collect();
+        let total = meta.chapters.len();
+        let chapters = options.selection.select(meta.chapters);
+        if !options.selection.is_empty() {
+            info!("《{}》共{}章，选中{}章", self.title, total, chapters.len());
+        }
+        let fetch_episode_tasks: Vec<_> = chapters
             .into_iter()
-            .enumerate()
-            .map(|(idx, episode_url)| {
-                let credential = Arc::clone(&esj_credential);
-                tokio::spawn(async move {
-                    Episode::fetch_esj_episode(&episode_url, Some(&credential), idx as u32 + 1)
-                        .await
-                })
+            .map(|(order, chapter)| {
+                let record = manifest.record(&chapter.url, &chapter.title).cloned();
+                if record.is_none() {
+                    debug!("开始获取《{}》- {}", self.title, chapter.title);
+                }
+                let source = Arc::clone(source);
+                let save_path = self.save_path.clone();
+                let cache = cache.clone();
+                let task_chapter = chapter.clone();
+                let fetch_episode_task = tokio::spawn(async move {
+                    let chapter = task_chapter;
+                    let cached_episode = match record {
+                        Some(record) => Manifest::load_recorded(&save_path, &record, order)
+                            .await
+                            .inspect_err(|err| warn!("章节缓存{}读取失败: {}", chapter.url, err))
+                            .ok(),
+                        None => None,
+                    };
+                    let mut episode = match cached_episode {
+                        Some(episode) => episode,
+                        None => cache.fetch_episode(&source, &chapter, order).await?,
+                    };
+                    episode.volume = chapter.volume;
+                    Ok::<_, anyhow::Error>(episode)
+                });
+                (order, chapter, fetch_episode_task)
             })
             .collect();
-        for fetch_esj_episode_task in fetch_esj_episode_tasks {
-            let episode = fetch_esj_episode_task.await?;
-            self.episodes
-                .push(episode.expect("unable to fetch episode"));
+        for (order, chapter, fetch_episode_task) in fetch_episode_tasks {
+            let err = match fetch_episode_task.await {
+                Ok(Ok(episode)) => {
+                    self.episodes.push(episode);
+                    continue;
+                }
+                Ok(Err(err)) => format!("{:#}", err),
+                Err(err) => err.to_string(),
+            };
+            // 单章失败时以占位页代替，继续生成其余章节
+            warn!(
+                "《{}》- {} 获取失败，使用占位页代替: {}",
+                self.title, chapter.title, err
+            );
+            self.episodes.push(Episode {
+                volume: chapter.volume,
+                ..Episode::placeholder(&chapter.title, &chapter.url, order, &err)
+            });
+            self.failures.chapters.push(Failure {
+                name: chapter.title,
+                url: chapter.url,
+                error: err,
+            });
+        }
+        if update {
+            let new_count = self
+                .episodes
+                .iter()
+                .filter(|episode| !manifest.is_unchanged(episode))
+                .count();
+            info!(
+                "《{}》共{}章，新增或更新{}章",
+                self.title,
+                self.episodes.len(),
+                new_count
+            );
         }
         Ok(())
     }
 
+    /// 保存章节缓存与 `manifest.json`，供下次增量更新使用
+    ///
+    /// 本次未选中或获取失败的章节沿用上次的记录，只删除已从远端目录中消失的章节
+    async fn save_manifest(&self) -> Result<()> {
+        for episode in self.fetched_episodes() {
+            Manifest::save_episode(&self.save_path, episode).await?;
+        }
+        let previous = Manifest::load(&self.save_path)
+            .await
+            .map(|manifest| manifest.chapters)
+            .unwrap_or_default();
+        let mut manifest = Manifest {
+            url: self.url.clone(),
+            title: self.title.clone(),
+            author: self.author.clone(),
+            chapters: self.chapter_records(),
+            illustrations: self.illustration_urls.clone(),
+            volumes: self.volumes.clone(),
+            source: self.source.clone(),
+            publisher: self.publisher.clone(),
+            updated: self.updated.clone(),
+            info: self.info.clone(),
+        };
+        manifest.merge_chapters(previous, &self.chapter_urls);
+        manifest.save(&self.save_path).await?;
+        Manifest::prune_chapters(&self.save_path, &self.chapter_urls).await
+    }
+
+    /// 成功获取的章节，不包括失败章节的占位页
+    fn fetched_episodes(&self) -> impl Iterator<Item = &Episode> {
+        self.episodes
+            .iter()
+            .filter(|episode| !self.failures.is_failed_chapter(&episode.url))
+    }
+
+    /// 失败的章节不写入记录，下次增量更新时会重新下载
+    fn chapter_records(&self) -> Vec<ChapterRecord> {
+        self.fetched_episodes()
+            .map(|episode| ChapterRecord {
+                url: episode.url.clone(),
+                title: episode.episode_title.clone(),
+                hash: hash(&episode.content),
+            })
+            .collect()
+    }
+
+    /// 书库中记录的上次的输出文件
+    async fn owned_output_files(&self) -> Result<Vec<PathBuf>> {
+        let library = Library::load(&Library::path(&self.root_path)).await?;
+        Ok(library
+            .get(&self.url)
+            .map(|entry| {
+                let mut files = entry.volume_files.clone();
+                files.push(entry.output_file.clone());
+                files
+            })
+            .unwrap_or_default())
+    }
+
+    /// 在书库中记录本次生成的结果
+    async fn save_library(&self, source: &Arc<dyn Source>, outputs: &OutputFiles) -> Result<()> {
+        let mut library = Library::load(&Library::path(&self.root_path)).await?;
+        let now = chrono::Utc::now();
+        let volumes = CONFIG.epub_config.split_volumes && !self.volumes.is_empty();
+        let (output_file, volume_files) = match (volumes, outputs.written.first()) {
+            (false, Some(output_file)) => (output_file.clone(), vec![]),
+            _ => (self.output_file()?, outputs.written.clone()),
+        };
+        library.upsert(LibraryEntry {
+            url: self.url.clone(),
+            source: source.name().to_string(),
+            title: self.title.clone(),
+            author: self.author.clone(),
+            chapters: self.chapter_records(),
+            illustrations: self.illustration_urls.clone(),
+            save_path: self.save_path.clone(),
+            output_file,
+            volume_files,
+            first_downloaded: now,
+            last_checked: now,
+        });
+        library.save().await
+    }
+
+    /// 输出文件名（不含扩展名），分卷时只截断系列名，保留卷号
+    fn file_stem(&self) -> String {
+        match &self.series {
+            Some(series) => format!("{} 第{}卷", safe_file_name(&series.name), series.index),
+            None => safe_file_name(&self.title),
+        }
+    }
+
+    /// 输出文件名模板中的字段
+    fn output_fields(&self) -> HashMap<&'static str, String> {
+        let safe = |value: &str| match value.is_empty() {
+            true => String::new(),
+            false => safe_file_name(value),
+        };
+        let series = self.series.as_ref();
+        HashMap::from([
+            ("title", self.file_stem()),
+            (
+                "series",
+                safe(series.map_or(&self.title, |series| &series.name)),
+            ),
+            (
+                "volume",
+                series.map_or(String::new(), |series| series.index.to_string()),
+            ),
+            ("author", safe(&self.author)),
+            ("source", safe(&self.source)),
+            ("chapters", self.episodes.len().to_string()),
+            ("updated", safe(self.updated.as_deref().unwrap_or_default())),
+        ])
+    }
+
+    /// 按 `output_template` 生成的输出文件路径
+    fn output_file(&self) -> Result<PathBuf> {
+        let template = OutputTemplate::parse(&CONFIG.epub_config.output_template)?;
+        Ok(self
+            .output_path
+            .join(template.render(&self.output_fields(), CONFIG.filename_config.max_bytes)))
+    }
+
+    /// 下载插画，按文件头或 Content-Type 修正扩展名，返回实际保存的文件名
     async fn download_illustration(
+        source: Arc<dyn Source>,
+        cache: Cache,
         title: String,
         url: String,
-        illustration_path: &PathBuf,
-    ) -> Result<()> {
-        let downloader = Downloader::new();
-        info!(
-            "正在下载《{}》中插画：{}",
-            title,
-            illustration_path.to_str().unwrap()
-        );
-        let content = downloader
-            .fetch_esj(Method::GET, &url, None)
-            .await?
-            .bytes()
-            .await?;
-        let _ = tokio::fs::write(illustration_path, content).await?;
+        base_path: &Path,
+        name: &str,
+    ) -> Result<String> {
+        info!("正在下载《{}》中插画：{}", title, url);
+        let illustration = cache.fetch_illustration(&source, &url).await?;
+        let name = match ImageFormat::detect(
+            &illustration.content,
+            illustration.content_type.as_deref(),
+        ) {
+            Some(format) => with_extension(name, format),
+            None => {
+                if !illustration.content.is_empty() {
+                    warn!("《{}》中插画{}格式无法识别，保留原文件名", title, url);
+                }
+                name.to_string()
+            }
+        };
+        let illustration_path = base_path.join(&name);
+        tokio::fs::write(&illustration_path, illustration.content).await?;
         info!(
             "下载《{}》中插画：{}完成",
             title,
             illustration_path.to_str().unwrap()
         );
-        Ok(())
+        Ok(name)
     }
 
-    async fn save_illustration(&self) -> Result<()> {
-        let base_path = Path::new(&self.save_path).join("OEBPS").join("Images");
-        let save_tasks: Vec<_> = self
+    /// 下载插画，增量更新时跳过本地已存在的文件
+    async fn save_illustration(&mut self, source: &Arc<dyn Source>, update: bool) -> Result<()> {
+        let base_path = self.image_path();
+        let cache = Cache::new(&self.save_path, self.offline);
+        // 多个地址共用一个文件时只下载一次
+        let files: BTreeMap<&String, &String> = self
             .illustration_urls
             .iter()
-            .map(|(url, illustration_name)| {
-                let url = url.clone();
+            .map(|(url, illustration_name)| (illustration_name, url))
+            .collect();
+        let save_tasks: Vec<_> = files
+            .into_iter()
+            .filter(|(illustration_name, _)| {
+                !update
+                    || std::fs::metadata(base_path.join(illustration_name))
+                        .map(|metadata| metadata.len() == 0)
+                        .unwrap_or(true)
+            })
+            .map(|(illustration_name, url)| {
                 let title = self.title.clone();
-                let illustration_path = base_path.join(illustration_name);
-                tokio::spawn(async move {
-                    Book::download_illustration(title, url, &illustration_path).await
-                })
+                let base_path = base_path.clone();
+                let name = illustration_name.clone();
+                let source = Arc::clone(source);
+                let cache = cache.clone();
+                let url = url.clone();
+                let save_task = tokio::spawn(async move {
+                    Book::download_illustration(source, cache, title, url, &base_path, &name).await
+                });
+                (illustration_name.clone(), save_task)
             })
             .collect();
-        if !self.with_cover {
-            info!("《{}》封面不存在，使用默认封面替代", self.title);
-            let cover_task = tokio::spawn(async move {
-                tokio::fs::copy("./template/default_cover.jpg", base_path.join("cover.jpg")).await
-            });
-            let _ = cover_task.await?;
-        }
         info!("开始下载《{}》插画", self.title);
-        for save_task in save_tasks {
-            // 这样写方便对每个task单独处理？
-            let _ = save_task.await?;
+        for (old_name, save_task) in save_tasks {
+            match save_task.await {
+                Ok(Ok(name)) => self.rename_illustration(&old_name, name),
+                Ok(Err(err)) => self.illustration_failed(&old_name, format!("{:#}", err)),
+                Err(err) => self.illustration_failed(&old_name, err.to_string()),
+            }
         }
         info!("下载《{}》插画完成", self.title);
         Ok(())
     }
 
-    fn make_epub(&self) -> Result<()> {
-        info!("开始《{}》epub文件打包", self.title);
-        let src_dir = Path::new(&CONFIG.esj_zone_config.esj_root_path).join(&self.title);
-        let dst_file =
-            Path::new(&CONFIG.esj_zone_config.esj_output_path).join(format!("{}.epub", self.title));
-        if !Path::new(&src_dir).is_dir() {
-            return Err(ZipError::FileNotFound.into());
-        }
-
-        let dst_path = Path::new(&dst_file);
-
-        let mut epub_writer = zip::ZipWriter::new(std::fs::File::create(dst_path).unwrap());
-        let options = SimpleFileOptions::default()
-            .compression_method(zip::CompressionMethod::Deflated)
-            .unix_permissions(0o755);
-
-        let mut buffer = Vec::new();
-        for entry in WalkDir::new(&src_dir).into_iter().filter_map(|e| e.ok()) {
-            let path = entry.path();
-            let name = path.strip_prefix(&src_dir).unwrap();
-            let epub_inner_path = path
-                .strip_prefix(&src_dir)
-                .unwrap()
-                .to_str()
-                .map(str::to_owned)
-                .with_context(|| format!("{name:?} Is a Non UTF-8 Path"))?;
-
-            match path.is_file() {
-                true => {
-                    epub_writer.start_file(epub_inner_path, options)?;
-                    let mut f = std::fs::File::open(path)?;
-                    f.read_to_end(&mut buffer)?;
-                    epub_writer.write_all(&buffer)?;
-                    buffer.clear();
+    fn illustration_failed(&mut self, name: &str, error: String) {
+        for (url, _) in self
+            .illustration_urls
+            .iter()
+            .filter(|(_, illustration_name)| *illustration_name == name)
+        {
+            warn!("《{}》插画{}下载失败: {}", self.title, url, error);
+            self.failures.illustrations.push(Failure {
+                name: name.to_string(),
+                url: url.clone(),
+                error: error.clone(),
+            });
+        }
+    }
+
+    /// 记录插画的新文件名，共用该文件的地址与封面一并更新，并删除旧文件
+    fn rename_illustration(&mut self, old_name: &str, name: String) {
+        if old_name == name {
+            return;
+        }
+        let _ = std::fs::remove_file(self.image_path().join(old_name));
+        self.illustration_urls
+            .values_mut()
+            .filter(|illustration_name| *illustration_name == old_name)
+            .for_each(|illustration_name| *illustration_name = name.clone());
+        if self.cover.as_deref() == Some(old_name) {
+            self.cover = Some(name);
+        }
+    }
+
+    /// 去重并排序后的插画文件名，多个地址可能共用一个文件
+    pub fn illustration_files(&self) -> Vec<&String> {
+        self.illustration_urls
+            .values()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// 按内容哈希合并重复的插画，内容相同的插画共用文件名排序最靠前的文件。
+    /// 封面单独保存，不参与合并
+    fn dedup_illustrations(&mut self) -> Result<()> {
+        let image_path = self.image_path();
+        let names: Vec<String> = self
+            .illustration_files()
+            .into_iter()
+            .filter(|name| !is_cover(name) && is_non_empty_file(&image_path.join(name)))
+            .cloned()
+            .collect();
+        let mut files: HashMap<String, String> = HashMap::new();
+        let mut merged = 0;
+        for name in names {
+            let mut hasher = Md5::new();
+            hasher.update(std::fs::read(image_path.join(&name))?);
+            let digest = hex::encode(hasher.finalize());
+            match files.get(&digest) {
+                Some(kept) => {
+                    debug!("《{}》插画{}与{}内容相同，已合并", self.title, name, kept);
+                    self.rename_illustration(&name, kept.clone());
+                    merged += 1;
                 }
-                false => {
-                    if !name.as_os_str().is_empty() {
-                        epub_writer.add_directory(epub_inner_path, options)?;
-                    }
+                None => {
+                    files.insert(digest, name);
                 }
             }
         }
+        if merged > 0 {
+            info!("《{}》合并了{}张重复的插画", self.title, merged);
+        }
+        Ok(())
+    }
+
+    /// 按文件头修正已下载插画的扩展名，兼容旧版本统一保存为 `.jpg` 的插画
+    fn fix_illustration_extensions(&mut self) -> Result<()> {
+        let image_path = self.image_path();
+        let renames: Vec<_> = self
+            .illustration_files()
+            .into_iter()
+            .filter_map(|name| {
+                let format = ImageFormat::sniff_file(&image_path.join(name)).ok()??;
+                let fixed = with_extension(name, format);
+                (fixed != *name).then(|| (name.clone(), fixed))
+            })
+            .collect();
+        for (name, fixed) in renames {
+            std::fs::rename(image_path.join(&name), image_path.join(&fixed))?;
+            debug!("《{}》插画{}重命名为{}", self.title, name, fixed);
+            self.rename_illustration(&name, fixed);
+        }
+        Ok(())
+    }
+
+    /// 将部分阅读器无法显示的 WebP、AVIF、BMP 插画转换为 JPEG 或 PNG，
+    /// 转换后的文件替换原图，转换失败时保留原图
+    fn convert_illustrations(&mut self) -> Result<()> {
+        let image_path = self.image_path();
+        let conversions: Vec<_> = self
+            .illustration_files()
+            .into_iter()
+            .filter(|name| {
+                ImageFormat::sniff_file(&image_path.join(name))
+                    .ok()
+                    .flatten()
+                    .is_some_and(ImageFormat::needs_conversion)
+            })
+            .cloned()
+            .collect();
+        for name in conversions {
+            let content = std::fs::read(image_path.join(&name))?;
+            match image::convert(&content) {
+                Ok((format, converted)) => {
+                    let converted_name = with_extension(&name, format);
+                    std::fs::write(image_path.join(&converted_name), converted)?;
+                    info!("《{}》插画{}已转换为{}", self.title, name, converted_name);
+                    self.rename_illustration(&name, converted_name);
+                }
+                Err(err) => warn!("《{}》插画{}转换失败，保留原图: {}", self.title, name, err),
+            }
+        }
+        Ok(())
+    }
+
+    /// 移除下载失败的插画，下次增量更新时会重新下载
+    fn remove_missing_illustrations(&mut self) {
+        let image_path = self.image_path();
+        let failures = &mut self.failures.illustrations;
+        self.illustration_urls.retain(|url, name| {
+            let exists = is_cover(name) || is_non_empty_file(&image_path.join(&*name));
+            if !exists && !failures.iter().any(|failure| failure.url == *url) {
+                warn!("《{}》插画{}下载失败，已跳过: {}", self.title, name, url);
+                failures.push(Failure {
+                    name: name.clone(),
+                    url: url.clone(),
+                    error: "文件不存在或为空".to_string(),
+                });
+            }
+            exists
+        });
+        if self.cover.is_none() {
+            info!("《{}》封面不存在，使用默认封面替代", self.title);
+        }
+    }
+
+    /// 按卷拆分出的子书，沿用本书已获取的章节与插画。
+    /// 未分卷的章节归入其后的第一卷，若其后没有卷则归入前一卷
+    fn split_volumes(&self) -> Vec<Book> {
+        let Some(mut current) = self
+            .episodes
+            .iter()
+            .find_map(|episode| episode.volume.clone())
+        else {
+            return vec![];
+        };
+        let mut groups: Vec<(String, Vec<Episode>)> = vec![];
+        for episode in &self.episodes {
+            if let Some(volume) = &episode.volume {
+                current = volume.clone();
+            }
+            match groups.last_mut() {
+                Some((volume, episodes)) if *volume == current => episodes.push(episode.clone()),
+                _ => groups.push((current.clone(), vec![episode.clone()])),
+            }
+        }
+        groups
+            .into_iter()
+            .enumerate()
+            .map(|(idx, (volume, episodes))| {
+                let index = self
+                    .volumes
+                    .iter()
+                    .position(|known| *known == volume)
+                    .unwrap_or(idx) as u32
+                    + 1;
+                let sources: HashSet<String> = episodes
+                    .iter()
+                    .flat_map(|episode| image_sources(&episode.content))
+                    .collect();
+                let illustration_urls = self
+                    .illustration_urls
+                    .iter()
+                    .filter(|(url, name)| !is_cover(name) && sources.contains(*url))
+                    .map(|(url, name)| (url.clone(), name.clone()))
+                    .collect();
+                let cover = first_illustration(&episodes, &illustration_urls)
+                    .cloned()
+                    .or_else(|| self.cover.clone());
+                Book {
+                    url: self.url.clone(),
+                    title: format!("{} 第{}卷", self.title, index),
+                    author: self.author.clone(),
+                    source: self.source.clone(),
+                    publisher: self.publisher.clone(),
+                    updated: self.updated.clone(),
+                    info: self.info.clone(),
+                    episodes,
+                    save_path: self.save_path.clone(),
+                    cover,
+                    illustration_urls,
+                    volumes: self.volumes.clone(),
+                    chapter_urls: vec![],
+                    series: Some(Series {
+                        name: self.title.clone(),
+                        index,
+                    }),
+                    format: self.format,
+                    device_profile: self.device_profile.clone(),
+                    about_page: self.about_page,
+                    failures: FailureReport::default(),
+                    offline: self.offline,
+                    root_path: self.root_path.clone(),
+                    output_path: self.output_path.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// 每卷生成一个 epub，封面使用该卷第一张插画，没有插画时使用本书封面
+    fn make_volume_epubs(&self, outputs: &mut OutputFiles) -> Result<()> {
+        let volumes = self.split_volumes();
+        if volumes.is_empty() {
+            warn!("《{}》目录未分卷，生成完整的epub", self.title);
+            return self.make_epub(outputs);
+        }
+        for volume in &volumes {
+            volume.make_epub(outputs)?;
+        }
+        info!("《{}》共生成{}卷", self.title, volumes.len());
+        Ok(())
+    }
+
+    /// 将 epub 直接写入输出文件，开启 `staging` 时另外在小说目录下写出解包后的内容
+    fn make_epub(&self, outputs: &mut OutputFiles) -> Result<()> {
+        info!("开始《{}》epub文件打包", self.title);
+        let mut report = SizeReport::default();
+        let entries = self.entries(&mut report)?;
+        if self.device_profile.is_some() {
+            info!("《{}》图片处理完成：{}", self.title, report);
+        }
+        if CONFIG.epub_config.staging {
+            let staging_path = self.save_path.join("staging").join(self.file_stem());
+            epub::stage(&staging_path, &entries)?;
+            info!(
+                "《{}》解包内容已写入: {}",
+                self.title,
+                staging_path.display()
+            );
+        }
+        let dst_file = outputs.claim(self.output_file()?);
+        if let Some(parent) = dst_file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // 先写入临时文件，避免中断时留下不完整的 epub
+        let tmp_file = dst_file.with_extension("epub.tmp");
+        let mut epub_writer = EpubWriter::new(BufWriter::new(std::fs::File::create(&tmp_file)?))?;
+        for entry in &entries {
+            epub_writer.add(entry)?;
+        }
         epub_writer.finish()?;
+        std::fs::rename(&tmp_file, &dst_file)?;
         info!("《{}》打包完成", self.title);
         Ok(())
     }
 
-    async fn create_epub(&mut self, url: &String, credential: Option<&Credential>) -> Result<()> {
-        let _ = self.fetch_book(&url, credential).await?;
-        self.update_illustration_urls();
-        let _ = self.init_dir().await?;
-        let _ = self.save_episodes().await?;
-        let _ = self.save_illustration().await?;
-        self.make_epub()?;
+    async fn create_epub(
+        &mut self,
+        url: &str,
+        source: &Arc<dyn Source>,
+        options: &DownloadOptions,
+    ) -> Result<()> {
+        let update = options.update;
+        self.fetch_book(source, url, options).await?;
+        self.update_illustration_urls(source);
+        self.init_dir(update).await?;
+        self.save_illustration(source, update).await?;
+        self.fix_illustration_extensions()?;
+        if CONFIG.epub_config.convert_images {
+            self.convert_illustrations()?;
+        }
+        self.dedup_illustrations()?;
+        self.remove_missing_illustrations();
+        self.save_manifest().await?;
+        Cache::new(&self.save_path, self.offline).finish().await?;
+        let mut outputs = OutputFiles::new(self.owned_output_files().await?);
+        match CONFIG.epub_config.split_volumes {
+            true => self.make_volume_epubs(&mut outputs)?,
+            false => self.make_epub(&mut outputs)?,
+        }
+        self.save_library(source, &outputs).await?;
+        if !self.failures.is_empty() {
+            warn!(
+                "《{}》部分内容下载失败，已生成不完整的epub：{}",
+                self.title, self.failures
+            );
+        }
         Ok(())
     }
 
-    pub async fn gen_epub(url: &String, credential: Option<&Credential>) -> Result<()> {
-        let mut book = Book::new();
-        let _ = book.create_epub(url, credential).await?;
+    /// 不访问网络，使用小说目录中缓存的章节与插画重新生成 epub
+    pub async fn export(save_path: &Path) -> Result<()> {
+        Book::export_with(Book::new(), save_path).await
+    }
+
+    /// 按小说目录中的 `manifest.json` 填充 `book` 后生成 epub，输出目录与书库沿用 `book` 的设置
+    async fn export_with(mut book: Book, save_path: &Path) -> Result<()> {
+        let manifest = Manifest::load(save_path).await?;
+        book.url = manifest.url;
+        book.title = manifest.title;
+        book.author = manifest.author;
+        book.source = manifest.source;
+        book.publisher = manifest.publisher;
+        book.updated = manifest.updated;
+        book.info = manifest.info;
+        book.save_path = save_path.to_path_buf();
+        for (idx, chapter) in manifest.chapters.iter().enumerate() {
+            book.episodes
+                .push(Manifest::load_episode(save_path, &chapter.url, idx as u32 + 1).await?);
+        }
+        book.cover = manifest
+            .illustrations
+            .values()
+            .find(|illustration_name| is_cover(illustration_name))
+            .cloned();
+        book.illustration_urls = manifest.illustrations;
+        book.volumes = manifest.volumes;
+        info!("使用本地缓存导出《{}》", book.title);
+        book.init_dir(true).await?;
+        book.fix_illustration_extensions()?;
+        if CONFIG.epub_config.convert_images {
+            book.convert_illustrations()?;
+        }
+        book.dedup_illustrations()?;
+        book.remove_missing_illustrations();
+        let mut outputs = OutputFiles::new(book.owned_output_files().await?);
+        match CONFIG.epub_config.split_volumes {
+            true => book.make_volume_epubs(&mut outputs)?,
+            false => book.make_epub(&mut outputs)?,
+        }
         Ok(())
     }
+
+    /// 生成 epub，下载范围与增量更新由 `options` 控制，返回下载失败的章节与插画
+    pub async fn gen_epub(
+        url: &str,
+        source: Arc<dyn Source>,
+        options: &DownloadOptions,
+    ) -> Result<FailureReport> {
+        info!("使用{}解析: {}", source.name(), url);
+        let mut book = Book::new();
+        book.create_epub(url, &source, options).await?;
+        Ok(book.failures)
+    }
+}
+
+/// 封面在小说目录中保存为 `cover.<扩展名>`
+pub fn is_cover(name: &str) -> bool {
+    name.rsplit_once('.').map_or(name, |(stem, _)| stem) == "cover"
+}
+
+fn is_non_empty_file(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.len() > 0)
+}
+
+/// 章节中最先出现的插画，不包括封面
+fn first_illustration<'a>(
+    episodes: &[Episode],
+    illustration_urls: &'a HashMap<String, String>,
+) -> Option<&'a String> {
+    episodes.iter().find_map(|episode| {
+        image_sources(&episode.content)
+            .iter()
+            .filter_map(|url| illustration_urls.get(url))
+            .find(|name| !is_cover(name))
+    })
 }
 
 #[cfg(test)]
@@ -377,9 +1043,17 @@ mod tests {
     use anyhow::Ok;
 
     use super::*;
+    use crate::source::{EsjZone, Illustration};
+    use crate::Downloader;
     use crate::CONFIG;
+    use std::io::Read;
     use std::sync::Arc;
 
+    fn esj_source() -> Arc<dyn Source> {
+        let downloader = Downloader::new(&CONFIG.download_config).unwrap();
+        Arc::new(EsjZone::from_config(&downloader))
+    }
+
     #[tokio::test]
     async fn fuck_episodes() -> Result<()> {
         let episode = Episode {
@@ -387,33 +1061,47 @@ mod tests {
             content: "cnm".to_string(),
             episode_save_path: "./Text/1.xhtml".to_string(),
             order: 1,
+            url: "https://www.esjzone.me/forum/1696518058/180636.html".to_string(),
+            volume: None,
         };
         let episode2 = Episode {
             episode_title: "第一章".to_string(),
             content: "nmsl".to_string(),
             episode_save_path: "./Text/2.xhtml".to_string(),
             order: 1,
+            url: "https://www.esjzone.me/forum/1696518058/180637.html".to_string(),
+            volume: None,
         };
         let episodes = vec![episode, episode2];
         let mut book = Book {
+            url: String::new(),
             title: "下北泽秘闻".to_string(),
             author: "野兽先生".to_string(),
-            episodes: episodes,
+            source: String::new(),
+            publisher: String::new(),
+            updated: None,
+            info: BookInfo::default(),
+            episodes,
             save_path: Path::new("./esjNovelGen").join("下北泽秘闻"),
             illustration_urls: HashMap::new(),
-            with_cover: false,
+            cover: None,
+            volumes: vec![],
+            chapter_urls: vec![],
+            series: None,
+            format: OutputFormat::Epub2,
+            device_profile: None,
+            about_page: false,
+            failures: FailureReport::default(),
+            offline: false,
+            root_path: PathBuf::from("./esjNovelGen"),
+            output_path: PathBuf::from("./esjNovelOutput"),
         };
-        let esj_credential = Arc::new(Credential {
-            esj_key: CONFIG.esj_zone_config.ews_key.clone(),
-            esj_token: CONFIG.esj_zone_config.ews_token.clone(),
-        });
-        let credential = Arc::clone(&esj_credential);
-        let _ = book
-            .fetch_book(
-                "https://www.esjzone.me/detail/1719148048.html",
-                Some(&*credential),
-            )
-            .await?;
+        book.fetch_book(
+            &esj_source(),
+            "https://www.esjzone.me/detail/1719148048.html",
+            &DownloadOptions::default(),
+        )
+        .await?;
 
         Ok(())
     }
@@ -425,54 +1113,60 @@ mod tests {
             content: "cnm".to_string(),
             episode_save_path: "./Text/1.xhtml".to_string(),
             order: 1,
+            url: "https://www.esjzone.me/forum/1696518058/180636.html".to_string(),
+            volume: None,
         };
-        let esj_credential = Arc::new(Credential {
-            esj_key: CONFIG.esj_zone_config.ews_key.clone(),
-            esj_token: CONFIG.esj_zone_config.ews_token.clone(),
-        });
-        let credential = Arc::clone(&esj_credential);
+        let source = esj_source();
 
-        let _ = Episode::fetch_esj_episode(
-            "https://www.esjzone.me/forum/1696518058/180636.html",
-            Some(&*credential),
-            1,
-        )
-        .await?;
+        let _ = source
+            .fetch_episode_page("https://www.esjzone.me/forum/1696518058/180636.html")
+            .await?;
         let episodes = vec![episode];
         let mut book = Book {
+            url: String::new(),
             title: "下北泽秘闻".to_string(),
             author: "野兽先生".to_string(),
-            episodes: episodes,
+            source: String::new(),
+            publisher: String::new(),
+            updated: None,
+            info: BookInfo::default(),
+            episodes,
             save_path: Path::new("./esjNovelGen").join("下北泽秘闻"),
             illustration_urls: HashMap::new(),
-            with_cover: false,
+            cover: None,
+            volumes: vec![],
+            chapter_urls: vec![],
+            series: None,
+            format: OutputFormat::Epub2,
+            device_profile: None,
+            about_page: false,
+            failures: FailureReport::default(),
+            offline: false,
+            root_path: PathBuf::from("./esjNovelGen"),
+            output_path: PathBuf::from("./esjNovelOutput"),
         };
-        book.update_illustration_urls();
+        book.update_illustration_urls(&source);
         info!("{:?}", &book.illustration_urls);
-        book.save_illustration().await?;
+        book.save_illustration(&source, false).await?;
         Ok(())
     }
 
     #[tokio::test]
     async fn fuck_save_episodes() -> Result<()> {
-        let esj_credential = Arc::new(Credential {
-            esj_key: CONFIG.esj_zone_config.ews_key.clone(),
-            esj_token: CONFIG.esj_zone_config.ews_token.clone(),
-        });
-        let credential = Arc::clone(&esj_credential);
+        let source = esj_source();
         let mut book = Book::new();
-        let _ = book
-            .fetch_book(
-                // "https://www.esjzone.me/detail/1696518058.html",
-                "https://www.esjzone.cc/detail/1718674070.html",
-                Some(&credential),
-            )
-            .await?;
-        book.update_illustration_urls();
-        let _ = book.init_dir().await?;
-        let _ = book.save_episodes().await?;
-        let _ = book.save_illustration().await?;
-        book.make_epub()?;
+        book.fetch_book(
+            &source,
+            // "https://www.esjzone.me/detail/1696518058.html",
+            "https://www.esjzone.cc/detail/1718674070.html",
+            &DownloadOptions::default(),
+        )
+        .await?;
+        book.update_illustration_urls(&source);
+        book.init_dir(false).await?;
+        book.save_illustration(&source, false).await?;
+        book.remove_missing_illustrations();
+        book.make_epub(&mut OutputFiles::default())?;
         // info!("{:?}", &book.illustration_urls);
         // book.save_illustration().await?;
         Ok(())
@@ -485,29 +1179,936 @@ mod tests {
             content: "cnm".to_string(),
             episode_save_path: "./Text/1.xhtml".to_string(),
             order: 1,
+            url: "https://www.esjzone.me/forum/1696518058/180636.html".to_string(),
+            volume: None,
         };
-        let esj_credential = Arc::new(Credential {
-            esj_key: CONFIG.esj_zone_config.ews_key.clone(),
-            esj_token: CONFIG.esj_zone_config.ews_token.clone(),
-        });
-        let credential = Arc::clone(&esj_credential);
+        let source = esj_source();
 
-        let _ = Episode::fetch_esj_episode(
-            "https://www.esjzone.me/forum/1696518058/180636.html",
-            Some(&*credential),
-            1,
-        )
-        .await?;
+        let _ = source
+            .fetch_episode_page("https://www.esjzone.me/forum/1696518058/180636.html")
+            .await?;
         let episodes = vec![episode];
         let book = Book {
+            url: String::new(),
             title: "下北泽秘闻".to_string(),
             author: "野兽先生".to_string(),
-            episodes: episodes,
+            source: String::new(),
+            publisher: String::new(),
+            updated: None,
+            info: BookInfo::default(),
+            episodes,
             save_path: Path::new("./esjNovelGen").join("下北泽秘闻"),
             illustration_urls: HashMap::new(),
-            with_cover: false,
+            cover: None,
+            volumes: vec![],
+            chapter_urls: vec![],
+            series: None,
+            format: OutputFormat::Epub2,
+            device_profile: None,
+            about_page: false,
+            failures: FailureReport::default(),
+            offline: false,
+            root_path: PathBuf::from("./esjNovelGen"),
+            output_path: PathBuf::from("./esjNovelOutput"),
+        };
+        book.init_dir(false).await?;
+        Ok(())
+    }
+
+    /// 离线测试用的站点，章节内容由 url 生成并统计实际抓取次数
+    struct MockSource {
+        title: String,
+        chapters: Vec<(String, String)>,
+        fetched: std::sync::atomic::AtomicUsize,
+        cover_url: Option<String>,
+        /// 插画地址、内容与 Content-Type，全部插入第一章
+        illustrations: Vec<(String, Vec<u8>, Option<String>)>,
+        fetched_illustrations: std::sync::atomic::AtomicUsize,
+        /// 获取时返回错误的章节地址
+        failing: Vec<String>,
+    }
+
+    impl MockSource {
+        fn new(title: &str, count: usize) -> Self {
+            MockSource {
+                title: title.to_string(),
+                chapters: (1..=count)
+                    .map(|idx| {
+                        (
+                            format!("https://mock.test/{}.html", idx),
+                            format!("第{}章", idx),
+                        )
+                    })
+                    .collect(),
+                fetched: std::sync::atomic::AtomicUsize::new(0),
+                cover_url: None,
+                illustrations: vec![],
+                fetched_illustrations: std::sync::atomic::AtomicUsize::new(0),
+                failing: vec![],
+            }
+        }
+
+        fn fetched(&self) -> usize {
+            self.fetched.load(std::sync::atomic::Ordering::SeqCst)
+        }
+
+        /// 返回用于统计抓取次数的 MockSource 与传给 Book 的 `Arc<dyn Source>`
+        fn shared(self) -> (Arc<MockSource>, Arc<dyn Source>) {
+            let source = Arc::new(self);
+            (Arc::clone(&source), source)
+        }
+    }
+
+    type Archive = zip::ZipArchive<std::fs::File>;
+
+    fn read_entry(archive: &mut Archive, name: &str) -> Result<String> {
+        let mut content = String::new();
+        archive.by_name(name)?.read_to_string(&mut content)?;
+        Ok(content)
+    }
+
+    /// 检查 epub 通过结构验证且没有警告，返回打开的 epub 与其中的 content.opf
+    fn open_validated(epub: &Path) -> Result<(Archive, String)> {
+        let report = crate::validator::validate(epub)?;
+        assert!(report.is_valid(), "{:?}", report.errors);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(epub)?)?;
+        let opf = read_entry(&mut archive, "OEBPS/content.opf")?;
+        Ok((archive, opf))
+    }
+
+    /// 以默认选项生成 epub 并检查，返回打开的 epub 与其中的 content.opf
+    async fn build_and_validate(
+        book: &mut Book,
+        source: &Arc<dyn Source>,
+        url: &str,
+    ) -> Result<(Archive, String)> {
+        book.create_epub(url, source, &DownloadOptions::default())
+            .await?;
+        open_validated(&book.output_file()?)
+    }
+
+    /// 每个测试独立的小说生成目录、输出目录与书库，不读写配置中的目录，结束时整体删除
+    struct Fixture {
+        dir: PathBuf,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("ranobe-book-{}-test", name));
+            let _ = std::fs::remove_dir_all(&dir);
+            Fixture { dir }
+        }
+
+        /// 小说目录、epub 与书库都写入测试目录的 Book
+        fn book(&self) -> Book {
+            Book {
+                root_path: self.dir.join("novels"),
+                output_path: self.dir.join("output"),
+                ..Book::new()
+            }
+        }
+
+        async fn library(&self) -> Result<Library> {
+            Library::load(&Library::path(&self.dir.join("novels"))).await
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Source for MockSource {
+        fn name(&self) -> &'static str {
+            "mock"
+        }
+
+        fn display_name(&self) -> &'static str {
+            "Mock"
+        }
+
+        fn matches(&self, url: &str) -> bool {
+            url.starts_with("https://mock.test/")
+        }
+
+        async fn fetch_book_page(&self, _url: &str) -> Result<String> {
+            Ok(self.title.clone())
+        }
+
+        fn parse_book_page(&self, page: &str) -> Result<crate::source::BookMeta> {
+            Ok(crate::source::BookMeta {
+                title: page.to_string(),
+                author: "野兽先生".to_string(),
+                cover_url: self.cover_url.clone(),
+                updated: Some("2024-05-30".to_string()),
+                info: crate::source::BookInfo {
+                    kind: Some("日本輕小說".to_string()),
+                    status: Some("已完結".to_string()),
+                    tags: vec!["戀愛".to_string()],
+                    word_count: Some(114514),
+                    synopsis: Some("第一段\n第二段 <b>".to_string()),
+                },
+                chapters: self
+                    .chapters
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, (url, title))| crate::source::ChapterRef {
+                        title: title.clone(),
+                        url: url.clone(),
+                        volume: Some(format!("第{}卷", idx / 2 + 1)),
+                    })
+                    .collect(),
+            })
+        }
+
+        /// 页面第一行为标题，其余为正文，插画全部插入第一章
+        async fn fetch_episode_page(&self, url: &str) -> Result<String> {
+            self.fetched
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if self.failing.iter().any(|failing| failing == url) {
+                anyhow::bail!("HTTP status server error (503 Service Unavailable)");
+            }
+            let idx = self
+                .chapters
+                .iter()
+                .position(|(chapter_url, _)| chapter_url == url)
+                .unwrap();
+            let images: String = match idx {
+                0 => self
+                    .illustrations
+                    .iter()
+                    .map(|(url, _, _)| format!(r#"<img src="{}">"#, url))
+                    .collect(),
+                _ => String::new(),
+            };
+            Ok(format!(
+                "{}\n<p>{}</p>{}",
+                self.chapters[idx].1, url, images
+            ))
+        }
+
+        fn parse_episode_page(&self, page: &str, url: &str, order: u32) -> Result<Episode> {
+            let (title, content) = page.split_once('\n').unwrap();
+            Ok(Episode {
+                episode_title: title.to_string(),
+                content: content.to_string(),
+                episode_save_path: format!("Text/{}.xhtml", order),
+                order,
+                url: url.to_string(),
+                volume: None,
+            })
+        }
+
+        fn resolve_illustrations(&self, content: &str) -> Vec<String> {
+            self.illustrations
+                .iter()
+                .map(|(url, _, _)| url.clone())
+                .filter(|url| content.contains(url.as_str()))
+                .collect()
+        }
+
+        async fn fetch_illustration(&self, url: &str) -> Result<Illustration> {
+            self.fetched_illustrations
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if self.cover_url.as_deref() == Some(url) {
+                return Ok(Illustration {
+                    content: b"GIF89a\x01\x00".to_vec(),
+                    content_type: None,
+                });
+            }
+            let (content, content_type) = self
+                .illustrations
+                .iter()
+                .find(|(illustration_url, _, _)| illustration_url == url)
+                .map(|(_, content, content_type)| (content.clone(), content_type.clone()))
+                .unwrap_or_default();
+            Ok(Illustration {
+                content,
+                content_type,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_only_fetches_new_chapters() -> Result<()> {
+        let fixture = Fixture::new("update");
+        let title = "增量更新测试";
+        let url = "https://mock.test/detail.html";
+        let (source, dyn_source) = MockSource::new(title, 3).shared();
+        let mut book = fixture.book();
+        book.create_epub(url, &dyn_source, &DownloadOptions::default())
+            .await?;
+        assert_eq!(source.fetched(), 3);
+        // 本地正文与记录的 md5 不一致的章节重新获取
+        let original = book.episodes[2].content.clone();
+        let edited = Episode {
+            content: "<p>被改动的正文</p>".to_string(),
+            ..book.episodes[2].clone()
+        };
+        Manifest::save_episode(&book.save_path, &edited).await?;
+
+        let mut updated = MockSource::new(title, 4);
+        updated.chapters[1].1 = "第2章（修订）".to_string();
+        let (source, dyn_source) = updated.shared();
+        let mut book = fixture.book();
+        let options = DownloadOptions {
+            update: true,
+            ..DownloadOptions::default()
+        };
+        book.create_epub(url, &dyn_source, &options).await?;
+        assert_eq!(source.fetched(), 3);
+        assert_eq!(book.episodes.len(), 4);
+        assert_eq!(book.episodes[1].episode_title, "第2章（修订）");
+        assert_eq!(book.episodes[2].content, original);
+        assert_eq!(book.episodes[3].order, 4);
+        assert_eq!(book.episodes[2].volume.as_deref(), Some("第2卷"));
+
+        let manifest = Manifest::load(&book.save_path).await?;
+        assert_eq!(manifest.chapters.len(), 4);
+        let library = fixture.library().await?;
+        let entry = library.get(url).unwrap();
+        assert_eq!(entry.chapters.len(), 4);
+        assert_eq!(entry.source, "mock");
+        let epub = book.output_path.join(format!("{}.epub", title));
+        assert!(epub.is_file());
+
+        tokio::fs::remove_file(&epub).await?;
+        Book::export_with(fixture.book(), &book.save_path).await?;
+        assert!(epub.is_file());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_selection_keeps_order() -> Result<()> {
+        let fixture = Fixture::new("selection");
+        let (source, dyn_source) = MockSource::new("部分下载测试", 5).shared();
+        let options = DownloadOptions {
+            update: false,
+            selection: ChapterSelection::from_config(&crate::config::SelectionConfig {
+                volumes: Some("2-".to_string()),
+                ..Default::default()
+            })?,
+        };
+        let mut book = fixture.book();
+        book.fetch_book(&dyn_source, "https://mock.test/selection.html", &options)
+            .await?;
+        assert_eq!(source.fetched(), 3);
+        let orders: Vec<_> = book.episodes.iter().map(|episode| episode.order).collect();
+        assert_eq!(orders, vec![3, 4, 5]);
+        assert_eq!(book.episodes[0].episode_save_path, "Text/3.xhtml");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_selection_keeps_manifest() -> Result<()> {
+        let fixture = Fixture::new("selection-manifest");
+        let url = "https://mock.test/selection-manifest.html";
+        let (source, dyn_source) = MockSource::new("部分更新测试", 5).shared();
+        fixture
+            .book()
+            .create_epub(url, &dyn_source, &DownloadOptions::default())
+            .await?;
+        let options = DownloadOptions {
+            update: false,
+            selection: ChapterSelection::from_config(&crate::config::SelectionConfig {
+                volumes: Some("3".to_string()),
+                ..Default::default()
+            })?,
+        };
+        let mut book = fixture.book();
+        book.create_epub(url, &dyn_source, &options).await?;
+        assert_eq!(source.fetched(), 6);
+        // 未选中的章节保留上次的记录与缓存，增量更新时不再重新获取
+        let manifest = Manifest::load(&book.save_path).await?;
+        assert_eq!(manifest.chapters.len(), 5);
+        let update = DownloadOptions {
+            update: true,
+            ..Default::default()
+        };
+        fixture
+            .book()
+            .create_epub(url, &dyn_source, &update)
+            .await?;
+        assert_eq!(source.fetched(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_episode_page_images() {
+        let mut book = Book::new();
+        book.illustration_urls = HashMap::from([
+            ("https://mock.test/a.png".to_string(), "a.png".to_string()),
+            (
+                "https://mock.test/a.png?v=2".to_string(),
+                "b.png".to_string(),
+            ),
+        ]);
+        let episode = Episode {
+            episode_title: "第一章".to_string(),
+            content:
+                r#"<img src="https://mock.test/a.png?v=2"><img src="https://mock.test/a.png">"#
+                    .to_string(),
+            episode_save_path: "Text/1.xhtml".to_string(),
+            order: 1,
+            url: "https://mock.test/1.html".to_string(),
+            volume: None,
+        };
+        let page = book.episode_page(&episode);
+        assert!(page
+            .contains(r#"<img src="../Images/b.png" alt=""/><img src="../Images/a.png" alt=""/>"#));
+        assert!(!page.contains("?v=2"));
+    }
+
+    #[test]
+    fn test_split_volumes() {
+        let episode = |order: u32, volume: Option<&str>, content: &str| Episode {
+            episode_title: format!("第{}章", order),
+            content: content.to_string(),
+            episode_save_path: format!("Text/{}.xhtml", order),
+            order,
+            url: format!("https://mock.test/{}.html", order),
+            volume: volume.map(str::to_string),
         };
-        book.init_dir().await?;
+        let mut book = Book::new();
+        book.title = "分卷测试".to_string();
+        book.volumes = vec![
+            "第一卷".to_string(),
+            "第二卷".to_string(),
+            "第三卷".to_string(),
+        ];
+        book.episodes = vec![
+            episode(3, None, "<p>序章</p>"),
+            episode(
+                4,
+                Some("第二卷"),
+                r#"<img src="https://mock.test/a.jpg"/><img src="https://mock.test/b.jpg"/>"#,
+            ),
+            episode(5, Some("第三卷"), "<p>正文</p>"),
+        ];
+        book.illustration_urls = HashMap::from([
+            (
+                "https://mock.test/cover.jpg".to_string(),
+                "cover.jpg".to_string(),
+            ),
+            ("https://mock.test/b.jpg".to_string(), "b.jpg".to_string()),
+            ("https://mock.test/a.jpg".to_string(), "a.jpg".to_string()),
+        ]);
+
+        let volumes = book.split_volumes();
+        assert_eq!(volumes.len(), 2);
+        assert_eq!(volumes[0].title, "分卷测试 第2卷");
+        assert_eq!(volumes[0].episodes.len(), 2);
+        assert_eq!(volumes[0].series.as_ref().unwrap().index, 2);
+        assert_eq!(volumes[0].series.as_ref().unwrap().name, "分卷测试");
+        assert_eq!(volumes[0].illustration_urls.len(), 2);
+        assert_eq!(volumes[0].cover.as_deref(), Some("a.jpg"));
+        assert_eq!(volumes[1].title, "分卷测试 第3卷");
+        assert!(volumes[1].illustration_urls.is_empty());
+        assert!(volumes[1].cover.is_none());
+        assert_eq!(volumes[1].save_path, book.save_path);
+
+        book.episodes
+            .iter_mut()
+            .for_each(|episode| episode.volume = None);
+        assert!(book.split_volumes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_make_volume_epubs() -> Result<()> {
+        let fixture = Fixture::new("volumes");
+        let title = "分卷输出测试";
+        let (_, source) = MockSource::new(title, 5).shared();
+        let mut book = fixture.book();
+        book.create_epub(
+            "https://mock.test/volumes.html",
+            &source,
+            &DownloadOptions::default(),
+        )
+        .await?;
+
+        book.make_volume_epubs(&mut OutputFiles::default())?;
+        for index in 1..=3 {
+            let epub = book
+                .output_path
+                .join(format!("{} 第{}卷.epub", title, index));
+            let (mut archive, opf) = open_validated(&epub)?;
+            assert!(opf.contains(&format!(
+                r#"<meta name="calibre:series_index" content="{}"/>"#,
+                index
+            )));
+            assert!(opf.contains(&format!(
+                r#"<meta name="calibre:series" content="{}"/>"#,
+                title
+            )));
+            assert!(archive.by_name("OEBPS/Images/cover.jpg").is_ok());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_epub3_package() -> Result<()> {
+        let fixture = Fixture::new("epub3");
+        let (_, source) = MockSource::new("EPUB3测试", 3).shared();
+        let mut book = fixture.book();
+        book.format = OutputFormat::Epub3;
+        let (mut archive, opf) =
+            build_and_validate(&mut book, &source, "https://mock.test/epub3.html").await?;
+        let mimetype = archive.by_index(0)?;
+        assert_eq!(mimetype.name(), "mimetype");
+        assert_eq!(mimetype.compression(), zip::CompressionMethod::Stored);
+        drop(mimetype);
+        for name in [
+            "OEBPS/content.opf",
+            "OEBPS/nav.xhtml",
+            "OEBPS/toc.ncx",
+            "OEBPS/Text/titlepage.xhtml",
+            "OEBPS/Text/1.xhtml",
+        ] {
+            let content = read_entry(&mut archive, name)?;
+            let mut reader = quick_xml::Reader::from_str(&content);
+            while reader.read_event()? != quick_xml::events::Event::Eof {}
+        }
+        assert!(opf.contains(&book.identifier()));
+        assert!(opf.contains(r#"properties="nav""#));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_illustration_formats() -> Result<()> {
+        let fixture = Fixture::new("illustration-formats");
+        let title = "插画格式测试";
+        let png_url = "https://mock.test/img/a.jpg".to_string();
+        let gif_url = "https://mock.test/img/b?size=large&id=1".to_string();
+        let mut source = MockSource::new(title, 2);
+        source.cover_url = Some("https://mock.test/img/cover".to_string());
+        source.illustrations = vec![
+            (
+                png_url.clone(),
+                b"\x89PNG\r\n\x1a\n\x00\x00".to_vec(),
+                Some("image/jpeg".to_string()),
+            ),
+            (
+                gif_url.clone(),
+                b"????".to_vec(),
+                Some("image/gif".to_string()),
+            ),
+        ];
+        let (source, dyn_source) = source.shared();
+        let mut book = fixture.book();
+        let (mut archive, opf) =
+            build_and_validate(&mut book, &dyn_source, "https://mock.test/formats.html").await?;
+        let png_name = hash(&png_url) + ".png";
+        let gif_name = hash(&gif_url) + ".gif";
+        assert_eq!(book.illustration_urls[&png_url], png_name);
+        assert_eq!(book.illustration_urls[&gif_url], gif_name);
+        assert_eq!(book.cover.as_deref(), Some("cover.gif"));
+        assert_eq!(book.cover_name(), "cover.gif");
+        assert!(!book.image_path().join(hash(&png_url) + ".jpg").exists());
+        assert!(opf.contains(&format!(
+            r#"href="Images/{}" media-type="image/png""#,
+            png_name
+        )));
+        assert!(opf.contains(&format!(
+            r#"href="Images/{}" media-type="image/gif""#,
+            gif_name
+        )));
+        assert!(opf
+            .contains(r#"<item id="cover.gif" href="Images/cover.gif" media-type="image/gif"/>"#));
+        let chapter = read_entry(&mut archive, "OEBPS/Text/1.xhtml")?;
+        assert!(chapter.contains(&format!(r#"src="../Images/{}""#, png_name)));
+        assert!(chapter.contains(&format!(r#"src="../Images/{}""#, gif_name)));
+        assert!(archive.by_name("OEBPS/Images/cover.gif").is_ok());
+
+        // 旧版本统一保存为 `.jpg` 的插画按文件头修正
+        let legacy_name = hash(&png_url) + ".jpg";
+        std::fs::rename(
+            book.image_path().join(&png_name),
+            book.image_path().join(&legacy_name),
+        )?;
+        book.illustration_urls
+            .insert(png_url.clone(), legacy_name.clone());
+        book.fix_illustration_extensions()?;
+        assert_eq!(book.illustration_urls[&png_url], png_name);
+        assert!(book.image_path().join(&png_name).is_file());
+        book.save_manifest().await?;
+
+        // 增量更新沿用已修正的文件名，不重复下载
+        let fetched = source
+            .fetched_illustrations
+            .load(std::sync::atomic::Ordering::SeqCst);
+        let mut updated = fixture.book();
+        updated
+            .create_epub(
+                "https://mock.test/formats.html",
+                &dyn_source,
+                &DownloadOptions {
+                    update: true,
+                    ..DownloadOptions::default()
+                },
+            )
+            .await?;
+        assert_eq!(
+            source
+                .fetched_illustrations
+                .load(std::sync::atomic::Ordering::SeqCst),
+            fetched
+        );
+        assert_eq!(updated.illustration_urls, book.illustration_urls);
+        assert_eq!(updated.cover.as_deref(), Some("cover.gif"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_illustrations() -> Result<()> {
+        let fixture = Fixture::new("convert-illustrations");
+        let mut book = fixture.book();
+        book.title = "插画转换测试".to_string();
+        book.save_path = book.root_path.join(&book.title);
+        std::fs::create_dir_all(book.image_path())?;
+        for (sample, name) in [
+            ("sample_alpha.webp", "a.webp"),
+            ("sample.avif", "b.jpg"),
+            ("sample.bmp", "cover.bmp"),
+        ] {
+            std::fs::copy(
+                Path::new("tests/images").join(sample),
+                book.image_path().join(name),
+            )?;
+        }
+        // 只有文件头的 AVIF 无法解码
+        std::fs::write(
+            book.image_path().join("c.avif"),
+            b"\x00\x00\x00\x1cftypavif\x00",
+        )?;
+        book.illustration_urls = [
+            ("https://mock.test/a", "a.webp"),
+            ("https://mock.test/b", "b.jpg"),
+            ("https://mock.test/c", "c.avif"),
+            ("https://mock.test/cover", "cover.bmp"),
+        ]
+        .into_iter()
+        .map(|(url, name)| (url.to_string(), name.to_string()))
+        .collect();
+        book.cover = Some("cover.bmp".to_string());
+        book.episodes = vec![Episode {
+            episode_title: "第1章".to_string(),
+            content: r#"<img src="https://mock.test/a"><img src="https://mock.test/b"><img src="https://mock.test/c">"#
+                .to_string(),
+            episode_save_path: "Text/1.xhtml".to_string(),
+            order: 1,
+            url: "https://mock.test/1.html".to_string(),
+            volume: None,
+        }];
+        book.fix_illustration_extensions()?;
+        book.convert_illustrations()?;
+
+        assert_eq!(book.illustration_urls["https://mock.test/a"], "a.png");
+        assert_eq!(book.illustration_urls["https://mock.test/b"], "b.jpg");
+        // 转换失败时保留原图
+        assert_eq!(book.illustration_urls["https://mock.test/c"], "c.avif");
+        assert_eq!(book.cover.as_deref(), Some("cover.jpg"));
+        assert_eq!(book.cover_name(), "cover.jpg");
+        assert!(!book.image_path().join("a.webp").exists());
+        assert!(!book.image_path().join("cover.bmp").exists());
+        let page = book.episode_page(&book.episodes[0]);
+        assert!(page.contains(r#"src="../Images/a.png""#));
+        assert!(page.contains(r#"src="../Images/b.jpg""#));
+        assert!(page.contains(r#"src="../Images/c.avif""#));
+        Ok(())
+    }
+
+    #[test]
+    fn test_device_profile() -> Result<()> {
+        let fixture = Fixture::new("device-profile");
+        let mut book = fixture.book();
+        book.title = "设备配置测试".to_string();
+        book.save_path = book.root_path.join(&book.title);
+        std::fs::create_dir_all(book.image_path())?;
+        let photo = ::image::RgbImage::from_fn(400, 300, |x, y| {
+            ::image::Rgb([(x % 256) as u8, (y % 256) as u8, ((x * y) % 256) as u8])
+        });
+        photo.save_with_format(
+            book.image_path().join("cover.png"),
+            ::image::ImageFormat::Png,
+        )?;
+        std::fs::copy("tests/images/sample.webp", book.image_path().join("a.webp"))?;
+        book.cover = Some("cover.png".to_string());
+        book.illustration_urls = [
+            ("https://mock.test/cover", "cover.png"),
+            ("https://mock.test/a", "a.webp"),
+        ]
+        .into_iter()
+        .map(|(url, name)| (url.to_string(), name.to_string()))
+        .collect();
+        book.device_profile = Some(DeviceProfile {
+            max_width: 200,
+            max_height: 200,
+            jpeg_quality: 60,
+            grayscale: true,
+        });
+
+        let mut report = SizeReport::default();
+        let entries = book.entries(&mut report)?;
+        let cover = entries
+            .iter()
+            .find(|entry| entry.path() == "OEBPS/Images/cover.png")
+            .unwrap();
+        let Entry::Data { content, .. } = cover else {
+            panic!("封面未处理");
+        };
+        let fitted = ::image::load_from_memory(content)?;
+        assert_eq!((fitted.width(), fitted.height()), (200, 150));
+        // 其他格式沿用原图
+        assert!(entries.iter().any(|entry| matches!(
+            entry,
+            Entry::File { path, .. } if path == "OEBPS/Images/a.webp"
+        )));
+        assert_eq!(report.images, 2);
+        assert!(report.saved() > 0);
+        assert_eq!(
+            report.original,
+            std::fs::metadata(book.image_path().join("cover.png"))?.len()
+                + std::fs::metadata(book.image_path().join("a.webp"))?.len()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dedup_illustrations() -> Result<()> {
+        let fixture = Fixture::new("dedup");
+        let title = "插画去重测试";
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00".to_vec();
+        let urls = [
+            "https://cdn1.mock.test/a.png",
+            "https://cdn2.mock.test/a.png?v=2",
+            "https://mock.test/b.gif",
+        ];
+        let mut source = MockSource::new(title, 1);
+        source.illustrations = vec![
+            (urls[0].to_string(), png.clone(), None),
+            (urls[1].to_string(), png, None),
+            (urls[2].to_string(), b"GIF89a\x01\x00".to_vec(), None),
+        ];
+        let (_, source) = source.shared();
+        let mut book = fixture.book();
+        let (mut archive, opf) =
+            build_and_validate(&mut book, &source, "https://mock.test/dedup.html").await?;
+        let shared = book.illustration_urls[urls[0]].clone();
+        assert_eq!(book.illustration_urls[urls[1]], shared);
+        assert_ne!(book.illustration_urls[urls[2]], shared);
+        assert_eq!(book.illustration_files().len(), 2);
+        assert_eq!(std::fs::read_dir(book.image_path())?.count(), 2);
+        assert_eq!(opf.matches(&format!("Images/{}", shared)).count(), 1);
+        let chapter = read_entry(&mut archive, "OEBPS/Text/1.xhtml")?;
+        assert_eq!(
+            chapter
+                .matches(&format!(r#"src="../Images/{}""#, shared))
+                .count(),
+            2
+        );
+        let manifest = Manifest::load(&book.save_path).await?;
+        assert_eq!(manifest.illustrations, book.illustration_urls);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_chapter_placeholder() -> Result<()> {
+        let fixture = Fixture::new("failed-chapter");
+        let title = "章节失败测试";
+        let url = "https://mock.test/failed.html";
+        let mut source = MockSource::new(title, 3);
+        source.failing = vec!["https://mock.test/2.html".to_string()];
+        source.illustrations = vec![("https://mock.test/img/missing".to_string(), vec![], None)];
+        let (_, source) = source.shared();
+        let mut book = fixture.book();
+        let (mut archive, _) = build_and_validate(&mut book, &source, url).await?;
+        let failures = &book.failures;
+        assert_eq!(failures.chapters.len(), 1);
+        assert_eq!(failures.chapters[0].name, "第2章");
+        assert!(failures.chapters[0].error.contains("503"));
+        assert_eq!(failures.illustrations.len(), 1);
+
+        let manifest = Manifest::load(&book.save_path).await?;
+        assert_eq!(manifest.chapters.len(), 2);
+        assert!(manifest
+            .record("https://mock.test/2.html", "第2章")
+            .is_none());
+        let placeholder = read_entry(&mut archive, "OEBPS/Text/2.xhtml")?;
+        assert!(placeholder.contains("<h1>第2章</h1>"));
+        assert!(placeholder.contains("本章下载失败"));
+        assert!(placeholder.contains(r#"<a href="https://mock.test/2.html">"#));
+
+        // 下次增量更新只重新下载失败的章节
+        let (retry, dyn_retry) = MockSource::new(title, 3).shared();
+        let mut book = fixture.book();
+        let options = DownloadOptions {
+            update: true,
+            ..DownloadOptions::default()
+        };
+        book.create_epub(url, &dyn_retry, &options).await?;
+        assert!(book.failures.is_empty());
+        assert_eq!(retry.fetched(), 1);
+        assert_eq!(Manifest::load(&book.save_path).await?.chapters.len(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_illustration_removed() -> Result<()> {
+        let fixture = Fixture::new("failed-illustration");
+        let mut source = MockSource::new("插画失败测试", 1);
+        source.illustrations = vec![
+            (
+                "https://mock.test/img/ok".to_string(),
+                b"GIF89a\x01\x00".to_vec(),
+                None,
+            ),
+            ("https://mock.test/img/missing".to_string(), vec![], None),
+        ];
+        let (_, source) = source.shared();
+        let mut book = fixture.book();
+        let url = "https://mock.test/failed-illustration.html";
+        let (mut archive, _) = build_and_validate(&mut book, &source, url).await?;
+        assert_eq!(book.failures.illustrations.len(), 1);
+        let chapter = read_entry(&mut archive, "OEBPS/Text/1.xhtml")?;
+        // 下载失败的插画被删除，不会留下指向远程地址的图片
+        assert_eq!(chapter.matches("<img").count(), 1, "{}", chapter);
+        assert!(chapter.contains(r#"src="../Images/"#), "{}", chapter);
+        assert!(!chapter.contains(r#"src="http"#), "{}", chapter);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_cache() -> Result<()> {
+        use std::sync::atomic::Ordering;
+        let fixture = Fixture::new("resume");
+        let title = "断点续传测试";
+        let url = "https://mock.test/resume.html";
+        let mock = |count| {
+            let mut source = MockSource::new(title, count);
+            source.illustrations = vec![(
+                "https://mock.test/img/resume".to_string(),
+                b"GIF89a\x02\x00".to_vec(),
+                None,
+            )];
+            source.shared()
+        };
+        // 获取章节后中断，此时还没有写入 manifest
+        let (source, dyn_source) = mock(3);
+        let mut book = fixture.book();
+        book.fetch_book(&dyn_source, url, &DownloadOptions::default())
+            .await?;
+        assert_eq!(source.fetched(), 3);
+        assert!(Manifest::load(&book.save_path).await.is_err());
+
+        // 重新运行时章节全部读取缓存，只下载插画
+        let (source, dyn_source) = mock(3);
+        let mut book = fixture.book();
+        book.create_epub(url, &dyn_source, &DownloadOptions::default())
+            .await?;
+        assert_eq!(source.fetched(), 0);
+        assert_eq!(source.fetched_illustrations.load(Ordering::SeqCst), 1);
+        assert_eq!(book.episodes.len(), 3);
+        assert!(book.failures.is_empty());
+
+        // 上次下载已完成，重新下载时章节全部重新获取
+        let (source, dyn_source) = mock(3);
+        let mut book = fixture.book();
+        book.create_epub(url, &dyn_source, &DownloadOptions::default())
+            .await?;
+        assert_eq!(source.fetched(), 3);
+
+        // 离线时不访问网络，缓存中没有的新章节以占位页代替
+        let (source, dyn_source) = mock(4);
+        let mut book = fixture.book();
+        book.offline = true;
+        book.create_epub(url, &dyn_source, &DownloadOptions::default())
+            .await?;
+        assert_eq!(source.fetched(), 0);
+        assert_eq!(source.fetched_illustrations.load(Ordering::SeqCst), 0);
+        assert_eq!(book.title, title);
+        assert_eq!(book.episodes.len(), 4);
+        assert_eq!(book.failures.chapters.len(), 1);
+        assert!(book.failures.chapters[0].error.contains("离线模式"));
+        assert!(book.failures.illustrations.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_safe_file_names() -> Result<()> {
+        let fixture = Fixture::new("safe-file-names");
+        let title = "Re:文件名/测试?...";
+        let (_, source) = MockSource::new(title, 3).shared();
+        let mut book = fixture.book();
+        book.create_epub(
+            "https://mock.test/filename.html",
+            &source,
+            &DownloadOptions::default(),
+        )
+        .await?;
+        assert_eq!(book.title, title);
+        assert_eq!(book.save_path, book.root_path.join("Re：文件名／测试？"));
+        assert!(book.save_path.join("manifest.json").is_file());
+        let epub = book.output_path.join("Re：文件名／测试？.epub");
+        assert_eq!(book.output_file()?, epub);
+        assert!(epub.is_file());
+        let volumes = book.split_volumes();
+        assert_eq!(
+            volumes[1].output_file()?.file_name().unwrap(),
+            "Re：文件名／测试？ 第2卷.epub"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_output_template() -> Result<()> {
+        let fixture = Fixture::new("output-template");
+        let (_, source) = MockSource::new("输出模板测试", 3).shared();
+        let mut book = fixture.book();
+        book.fetch_book(
+            &source,
+            "https://mock.test/template.html",
+            &DownloadOptions::default(),
+        )
+        .await?;
+        let template =
+            OutputTemplate::parse("{author}/{series}/{title} [{source}] ({chapters}ch) {updated}")?;
+        assert_eq!(
+            template.render(&book.output_fields(), 200),
+            PathBuf::from("野兽先生/输出模板测试/输出模板测试 [mock] (3ch) 2024-05-30.epub")
+        );
+        let volumes = book.split_volumes();
+        assert_eq!(
+            template.render(&volumes[1].output_fields(), 200),
+            PathBuf::from("野兽先生/输出模板测试/输出模板测试 第2卷 [mock] (1ch) 2024-05-30.epub")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_about_page() -> Result<()> {
+        let fixture = Fixture::new("about-page");
+        let (_, source) = MockSource::new("关于本书测试", 2).shared();
+        let mut book = fixture.book();
+        book.format = OutputFormat::Epub3;
+        book.about_page = true;
+        let (mut archive, opf) =
+            build_and_validate(&mut book, &source, "https://mock.test/about.html").await?;
+        assert_eq!(book.info.word_count, Some(114514));
+        assert_eq!(Manifest::load(&book.save_path).await?.info, book.info);
+        let about = read_entry(&mut archive, "OEBPS/Text/about.xhtml")?;
+        assert!(about.contains("<h1>关于本书测试</h1>"));
+        assert!(about.contains("<p>类型：日本輕小說</p>"));
+        assert!(about.contains("<p>状态：已完結</p>"));
+        assert!(about.contains("<p>标签：戀愛</p>"));
+        assert!(about.contains("<p>最后更新：2024-05-30</p>"));
+        assert!(about.contains(r#"<a href="https://mock.test/about.html">"#));
+        assert!(about.contains("<p>第二段 &lt;b&gt;</p>"));
+        assert!(opf.contains("<dc:publisher>Mock</dc:publisher>"));
+        assert!(opf.contains("<dc:subject>已完結</dc:subject>"));
+        assert!(opf.contains("<dc:date>2024-05-30</dc:date>"));
         Ok(())
     }
 }
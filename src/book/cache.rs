@@ -0,0 +1,252 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+use super::manifest::hash;
+use super::{Episode, Manifest};
+use crate::fs::atomic_write;
+use crate::source::{ChapterRef, Illustration, Source};
+use crate::CONFIG;
+
+/// 小说目录下的 `cache`，按地址保存下载到的页面与插画原图，并记录尚未完成的下载中已获取的章节。
+/// 章节解析结果与 `manifest.json` 共用 `chapters` 目录，下载后立即写入；
+/// 中断后重新运行时只沿用记录中的章节，离线模式下只读取缓存
+#[derive(Clone)]
+pub struct Cache {
+    save_path: PathBuf,
+    dir: PathBuf,
+    enabled: bool,
+    offline: bool,
+    /// 上次中断的下载中已获取的章节地址
+    pending: Arc<HashSet<String>>,
+}
+
+impl Cache {
+    pub const DIR_NAME: &'static str = "cache";
+    const PAGE_DIR: &'static str = "pages";
+    const IMAGE_DIR: &'static str = "images";
+    const PENDING_FILE: &'static str = "pending.txt";
+
+    pub fn new(save_path: &Path, offline: bool) -> Self {
+        Cache {
+            save_path: save_path.to_path_buf(),
+            dir: save_path.join(Cache::DIR_NAME),
+            enabled: CONFIG.cache_config.enabled || offline,
+            offline,
+            pending: Arc::default(),
+        }
+    }
+
+    fn path(&self, kind: &str, url: &str, extension: &str) -> PathBuf {
+        self.dir
+            .join(kind)
+            .join(format!("{}.{}", hash(url), extension))
+    }
+
+    /// 写入失败只影响断点续传，不中断下载
+    async fn save(&self, path: PathBuf, content: &[u8]) {
+        if !self.enabled {
+            return;
+        }
+        if let Err(err) = atomic_write(&path, content).await {
+            warn!("缓存{}写入失败: {}", path.display(), err);
+        }
+    }
+
+    async fn load_page(&self, url: &str) -> Option<String> {
+        if !self.enabled {
+            return None;
+        }
+        tokio::fs::read_to_string(self.path(Cache::PAGE_DIR, url, "html"))
+            .await
+            .ok()
+    }
+
+    async fn save_page(&self, url: &str, page: &str) {
+        self.save(self.path(Cache::PAGE_DIR, url, "html"), page.as_bytes())
+            .await
+    }
+
+    /// 读取上次中断时留下的章节记录，没有记录说明上次下载已经完成，章节全部重新获取
+    pub async fn resume(mut self) -> Self {
+        if !self.enabled || self.offline {
+            return self;
+        }
+        let Ok(content) = tokio::fs::read_to_string(self.dir.join(Cache::PENDING_FILE)).await
+        else {
+            return self;
+        };
+        let pending: HashSet<_> = content.lines().map(str::to_string).collect();
+        info!("上次下载未完成，从中断处继续，已获取{}章", pending.len());
+        self.pending = Arc::new(pending);
+        self
+    }
+
+    /// 写入 `manifest.json` 后删除章节记录，之后的下载不再沿用这些章节
+    pub async fn finish(&self) -> Result<()> {
+        match tokio::fs::remove_file(self.dir.join(Cache::PENDING_FILE)).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// 章节写入 `chapters` 后追加到记录中
+    async fn save_episode(&self, episode: &Episode) {
+        if let Err(err) = Manifest::save_episode(&self.save_path, episode).await {
+            warn!("章节缓存{}写入失败: {}", episode.url, err);
+            return;
+        }
+        if !self.enabled {
+            return;
+        }
+        let record = async {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.dir.join(Cache::PENDING_FILE))
+                .await?;
+            file.write_all(format!("{}\n", episode.url).as_bytes())
+                .await
+        };
+        if let Err(err) = record.await {
+            warn!("章节记录{}写入失败: {}", episode.url, err);
+        }
+    }
+
+    /// 在线时按详情页地址获取页面并写入缓存，离线时在 `root` 下各小说目录的缓存中查找
+    pub async fn fetch_book_page(
+        source: &Arc<dyn Source>,
+        url: &str,
+        root: &Path,
+        offline: bool,
+    ) -> Result<String> {
+        if !offline {
+            return source.fetch_book_page(url).await;
+        }
+        let mut dirs = tokio::fs::read_dir(root).await?;
+        while let Some(dir) = dirs.next_entry().await? {
+            let cache = Cache::new(&dir.path(), true);
+            if let Some(page) = cache.load_page(url).await {
+                return Ok(page);
+            }
+        }
+        Err(anyhow!("离线模式下缓存中没有{}的详情页", url))
+    }
+
+    /// 保存详情页，需要在解析出书名、确定小说目录后调用
+    pub async fn save_book_page(&self, url: &str, page: &str) {
+        if !self.offline {
+            self.save_page(url, page).await;
+        }
+    }
+
+    /// 获取章节：上次中断前已获取、标题与目录一致的章节直接使用，否则下载页面并立即写入；
+    /// 离线时使用已保存的任意章节，没有时重新解析缓存的页面
+    pub async fn fetch_episode(
+        &self,
+        source: &Arc<dyn Source>,
+        chapter: &ChapterRef,
+        order: u32,
+    ) -> Result<Episode> {
+        if self.offline || self.pending.contains(&chapter.url) {
+            let saved = Manifest::load_episode(&self.save_path, &chapter.url, order).await;
+            if let Ok(episode) = saved {
+                if self.offline || episode.episode_title == chapter.title {
+                    return Ok(episode);
+                }
+            }
+        }
+        let page = match self.offline {
+            true => self
+                .load_page(&chapter.url)
+                .await
+                .ok_or_else(|| anyhow!("离线模式下缓存中没有该章节"))?,
+            false => {
+                let page = source.fetch_episode_page(&chapter.url).await?;
+                self.save_page(&chapter.url, &page).await;
+                page
+            }
+        };
+        let episode = source.parse_episode_page(&page, &chapter.url, order)?;
+        self.save_episode(&episode).await;
+        Ok(episode)
+    }
+
+    /// 获取插画原图，下载后立即写入缓存，响应的 Content-Type 另存一份
+    pub async fn fetch_illustration(
+        &self,
+        source: &Arc<dyn Source>,
+        url: &str,
+    ) -> Result<Illustration> {
+        let path = self.path(Cache::IMAGE_DIR, url, "bin");
+        let content_type_path = path.with_extension("type");
+        if self.enabled {
+            if let Ok(content) = tokio::fs::read(&path).await {
+                return Ok(Illustration {
+                    content,
+                    content_type: tokio::fs::read_to_string(&content_type_path).await.ok(),
+                });
+            }
+        }
+        if self.offline {
+            return Err(anyhow!("离线模式下缓存中没有该插画"));
+        }
+        let illustration = source.fetch_illustration(url).await?;
+        if let Some(content_type) = &illustration.content_type {
+            self.save(content_type_path, content_type.as_bytes()).await;
+        }
+        self.save(path, &illustration.content).await;
+        Ok(illustration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cache_round_trip() -> Result<()> {
+        let save_path = std::env::temp_dir().join("ranobe-cache-test");
+        let cache = Cache {
+            enabled: true,
+            ..Cache::new(&save_path, false)
+        };
+        let url = "https://mock.test/1.html";
+        assert!(cache.load_page(url).await.is_none());
+        cache.save_page(url, "<html></html>").await;
+        assert_eq!(cache.load_page(url).await.as_deref(), Some("<html></html>"));
+
+        let episode = Episode {
+            episode_title: "第一章".to_string(),
+            content: "<p>正文</p>".to_string(),
+            episode_save_path: "Text/1.xhtml".to_string(),
+            order: 1,
+            url: url.to_string(),
+            volume: None,
+        };
+        cache.save_episode(&episode).await;
+        let cached = Manifest::load_episode(&save_path, url, 5).await?;
+        assert_eq!(cached.content, episode.content);
+        assert_eq!(cached.episode_save_path, "Text/5.xhtml");
+
+        // 未完成的下载留下章节记录，完成后删除
+        let resumed = cache.clone().resume().await;
+        assert!(resumed.pending.contains(url));
+        resumed.finish().await?;
+        assert!(cache.clone().resume().await.pending.is_empty());
+
+        let disabled = Cache {
+            enabled: false,
+            ..cache.clone()
+        };
+        disabled.save_episode(&episode).await;
+        assert!(cache.resume().await.pending.is_empty());
+        tokio::fs::remove_dir_all(&save_path).await?;
+        Ok(())
+    }
+}
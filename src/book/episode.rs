@@ -1,97 +1,88 @@
-use crate::{config::TEMPLATE, Credential, Downloader};
-use anyhow::{Ok, Result};
-use reqwest::Method;
-use scraper::{selectable::Selectable, Html, Selector};
+use quick_xml::escape::escape;
+use serde::{Deserialize, Serialize};
 
+use crate::config::{OutputFormat, TEMPLATE};
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Episode {
     pub episode_title: String,
     pub content: String,
     pub episode_save_path: String,
     pub order: u32,
+    pub url: String,
+    /// 章节所属的卷，目录未分卷时为空
+    #[serde(default)]
+    pub volume: Option<String>,
 }
 
 impl Episode {
-    pub fn new() -> Self {
+    /// 下载失败章节的占位页，正文为失败原因与原文地址
+    pub fn placeholder(title: &str, url: &str, order: u32, error: &str) -> Self {
         Episode {
-            episode_title: String::new(),
-            content: String::new(),
-            episode_save_path: String::new(),
-            order: 0,
+            episode_title: title.to_string(),
+            content: format!(
+                r#"<div class="failed"><p>本章下载失败：{}</p><p>原文地址：<a href="{}">{}</a></p></div>"#,
+                escape(error),
+                escape(url),
+                escape(url)
+            ),
+            episode_save_path: format!("Text/{}.xhtml", order),
+            order,
+            url: url.to_string(),
+            volume: None,
         }
     }
 
-    pub fn episode(&self) -> String {
-        format!(
-            "{}<head>
-  <title>{}</title>
-</head><body>
-  <h1>{}</h1>{}</body>",
-            TEMPLATE.episode_prefix, self.episode_title, self.episode_title, self.content
+    pub fn episode(&self, format: OutputFormat) -> String {
+        xhtml(
+            format,
+            &self.episode_title,
+            &format!(
+                "<h1>{}</h1>{}",
+                escape(self.episode_title.as_str()),
+                self.content
+            ),
         )
     }
+}
 
-    pub async fn fetch_esj_episode(
-        url: &str,
-        credential: Option<&Credential>,
-        order: u32,
-    ) -> Result<Self> {
-        let body = Downloader::new()
-            .fetch_esj(Method::GET, &url, credential)
-            .await?
-            .text()
-            .await?;
-        let doc = Html::parse_document(&body);
-
-        let episode_title_selector = Selector::parse(r#"div[class="col-xl-9 col-lg-8 p-r-30"]"#)
-            .expect("Failed to parse episode title selector");
-        let h2_selector = Selector::parse("h2").expect("Failed to parse h2 tag selector");
-        let content_block_selector = Selector::parse(r#"div[class="forum-content mt-3"]"#)
-            .expect("Failed to parse content blocker selector");
-        let episode_title = doc
-            .select(&episode_title_selector)
-            .flat_map(|episode_title_elem| episode_title_elem.select(&h2_selector))
-            .map(|h2_elem| h2_elem.text().collect::<String>())
-            .next()
-            .unwrap_or_default();
-
-        let content = doc
-            .select(&content_block_selector)
-            .next()
-            .map(|content_block_elem| content_block_elem.html())
-            .unwrap_or_else(|| format!("{}: 无文本内容", episode_title));
-
-        let episode_save_path = format!("Text/{}.xhtml", order);
-        Ok(Episode {
-            episode_title,
-            content,
-            episode_save_path,
-            order,
-        })
-    }
+/// 生成完整的 xhtml 页面，EPUB 2 使用 XHTML 1.1 的文档类型，EPUB 3 使用 HTML5 的文档类型
+pub fn xhtml(format: OutputFormat, title: &str, body: &str) -> String {
+    let prefix = match format {
+        OutputFormat::Epub2 => &TEMPLATE.episode_prefix,
+        OutputFormat::Epub3 => &TEMPLATE.epub3_episode_prefix,
+    };
+    format!(
+        "{}<head>
+  <title>{}</title>
+</head><body>
+  {}</body></html>",
+        prefix,
+        escape(title),
+        body
+    )
 }
 
 #[cfg(test)]
 mod tests {
-
-    use anyhow::Ok;
-
     use super::*;
-    use crate::CONFIG;
-    use std::sync::Arc;
-    #[tokio::test]
-    async fn fuck_episodes() -> Result<()> {
-        let esj_credential = Arc::new(Credential {
-            esj_key: CONFIG.esj_zone_config.ews_key.clone(),
-            esj_token: CONFIG.esj_zone_config.ews_token.clone(),
-        });
-        let credential = Arc::clone(&esj_credential);
-        let _ = Episode::fetch_esj_episode(
-            "https://www.esjzone.me/forum/1719148048/225492.html",
-            Some(&*credential),
-            1,
-        )
-        .await?;
 
-        Ok(())
+    #[test]
+    fn test_episode() {
+        let episode = Episode {
+            episode_title: "第一章 <上>".to_string(),
+            content: "<p>正文</p>".to_string(),
+            episode_save_path: "Text/1.xhtml".to_string(),
+            order: 1,
+            url: "https://www.esjzone.me/forum/1/1.html".to_string(),
+            volume: None,
+        };
+        let page = episode.episode(OutputFormat::Epub2);
+        assert!(page.contains("xhtml11.dtd"));
+        assert!(page.contains("<title>第一章 &lt;上&gt;</title>"));
+        assert!(page.ends_with("<h1>第一章 &lt;上&gt;</h1><p>正文</p></body></html>"));
+        let page = episode.episode(OutputFormat::Epub3);
+        assert!(page.contains("<!DOCTYPE html><html"));
+        assert!(page.ends_with("</body></html>"));
     }
 }
@@ -0,0 +1,141 @@
+use std::fs::File;
+use std::io::{Seek, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+const MIMETYPE: &str = "application/epub+zip";
+
+/// epub 中的一个文件，内容在内存中或来自磁盘上的文件
+pub enum Entry {
+    Data { path: String, content: Vec<u8> },
+    File { path: String, source: PathBuf },
+}
+
+impl Entry {
+    pub fn data(path: &str, content: impl Into<Vec<u8>>) -> Self {
+        Entry::Data {
+            path: path.to_string(),
+            content: content.into(),
+        }
+    }
+
+    pub fn file(path: &str, source: impl Into<PathBuf>) -> Self {
+        Entry::File {
+            path: path.to_string(),
+            source: source.into(),
+        }
+    }
+
+    /// 在 epub 中的路径
+    pub fn path(&self) -> &str {
+        match self {
+            Entry::Data { path, .. } | Entry::File { path, .. } => path,
+        }
+    }
+}
+
+/// 直接写入 zip 的 epub，`mimetype` 作为第一个文件不压缩存储，且不写入目录项
+pub struct EpubWriter<W: Write + Seek> {
+    zip: ZipWriter<W>,
+}
+
+impl<W: Write + Seek> EpubWriter<W> {
+    pub fn new(writer: W) -> Result<Self> {
+        let mut zip = ZipWriter::new(writer);
+        zip.start_file(
+            "mimetype",
+            SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+        )?;
+        zip.write_all(MIMETYPE.as_bytes())?;
+        Ok(EpubWriter { zip })
+    }
+
+    pub fn add(&mut self, entry: &Entry) -> Result<()> {
+        self.zip.start_file(
+            entry.path(),
+            SimpleFileOptions::default().compression_method(CompressionMethod::Deflated),
+        )?;
+        match entry {
+            Entry::Data { content, .. } => self.zip.write_all(content)?,
+            Entry::File { source, .. } => {
+                std::io::copy(&mut File::open(source)?, &mut self.zip)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<W> {
+        Ok(self.zip.finish()?)
+    }
+}
+
+/// 按 epub 内的目录结构写出全部文件，用于调试
+pub fn stage(dir: &Path, entries: &[Entry]) -> Result<()> {
+    if dir.exists() {
+        std::fs::remove_dir_all(dir)?;
+    }
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join("mimetype"), MIMETYPE)?;
+    for entry in entries {
+        let path = dir.join(entry.path());
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        match entry {
+            Entry::Data { content, .. } => std::fs::write(path, content)?,
+            Entry::File { source, .. } => {
+                std::fs::copy(source, path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use super::*;
+
+    #[test]
+    fn test_epub_writer() -> Result<()> {
+        let source = std::env::temp_dir().join("ranobe-epub-writer-test.jpg");
+        std::fs::write(&source, b"jpeg")?;
+        let entries = vec![
+            Entry::data("OEBPS/Text/1.xhtml", "<html/>"),
+            Entry::file("OEBPS/Images/a.jpg", &source),
+        ];
+        let mut writer = EpubWriter::new(Cursor::new(vec![]))?;
+        for entry in &entries {
+            writer.add(entry)?;
+        }
+        let mut archive = zip::ZipArchive::new(writer.finish()?)?;
+        let names: Vec<_> = archive.file_names().map(str::to_string).collect();
+        assert_eq!(names.len(), 3);
+        assert!(names.iter().all(|name| !name.ends_with('/')));
+        let mut mimetype = archive.by_index(0)?;
+        assert_eq!(mimetype.name(), "mimetype");
+        assert_eq!(mimetype.compression(), CompressionMethod::Stored);
+        let mut content = String::new();
+        mimetype.read_to_string(&mut content)?;
+        assert_eq!(content, MIMETYPE);
+        drop(mimetype);
+        let mut image = vec![];
+        archive
+            .by_name("OEBPS/Images/a.jpg")?
+            .read_to_end(&mut image)?;
+        assert_eq!(image, b"jpeg");
+
+        let dir = std::env::temp_dir().join("ranobe-epub-stage-test");
+        stage(&dir, &entries)?;
+        assert_eq!(std::fs::read_to_string(dir.join("mimetype"))?, MIMETYPE);
+        assert_eq!(std::fs::read(dir.join("OEBPS/Images/a.jpg"))?, b"jpeg");
+        assert!(dir.join("OEBPS/Text/1.xhtml").is_file());
+        std::fs::remove_dir_all(dir)?;
+        std::fs::remove_file(source)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use crate::config::FilenameConfig;
+use crate::CONFIG;
+
+/// 替换后仍不允许出现在文件名中的字符
+const ILLEGAL_CHARS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+/// Windows 保留的设备名，不区分大小写
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// 按 `filename_config` 将书名等转换为可以用作文件名或目录名的字符串（不含扩展名）
+pub fn safe_file_name(name: &str) -> String {
+    sanitize_with(&CONFIG.filename_config, name)
+}
+
+fn sanitize_with(rules: &FilenameConfig, name: &str) -> String {
+    let mut name = name.to_string();
+    for (from, to) in &rules.replacements {
+        if !from.is_empty() {
+            name = name.replace(from.as_str(), to);
+        }
+    }
+    let name: String = name
+        .chars()
+        .map(|c| match c.is_control() || ILLEGAL_CHARS.contains(&c) {
+            true => rules.replacement.clone(),
+            false => c.to_string(),
+        })
+        .collect();
+    let mut name = truncate(trim(&name), rules.max_bytes).to_string();
+    let stem = name.split('.').next().unwrap_or_default();
+    if name.is_empty() || RESERVED_NAMES.contains(&stem.to_ascii_uppercase().as_str()) {
+        name.push_str(match rules.replacement.is_empty() {
+            true => "_",
+            false => &rules.replacement,
+        });
+    }
+    name
+}
+
+/// 按字符截断到不超过 `max_bytes` 字节，截断后去掉结尾的空白与 `.`
+pub fn truncate(name: &str, max_bytes: usize) -> &str {
+    if name.len() <= max_bytes {
+        return name;
+    }
+    let end = (0..=max_bytes)
+        .rev()
+        .find(|idx| name.is_char_boundary(*idx))
+        .unwrap_or(0);
+    trim(&name[..end])
+}
+
+/// 去掉首尾空白以及结尾的 `.`，Windows 不允许文件名以 `.` 或空格结尾
+fn trim(name: &str) -> &str {
+    name.trim().trim_end_matches(['.', ' ']).trim_end()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_file_name() {
+        let rules = FilenameConfig::default();
+        assert_eq!(sanitize_with(&rules, "下北泽秘闻"), "下北泽秘闻");
+        assert_eq!(
+            sanitize_with(&rules, "Re:从零开始/第1卷? *特装版*"),
+            "Re：从零开始／第1卷？ ＊特装版＊"
+        );
+        assert_eq!(sanitize_with(&rules, " 标题……... "), "标题……");
+        assert_eq!(sanitize_with(&rules, "a\tb\u{0}c"), "a_b_c");
+        assert_eq!(sanitize_with(&rules, "con"), "con_");
+        assert_eq!(sanitize_with(&rules, "NUL.txt"), "NUL.txt_");
+        assert_eq!(sanitize_with(&rules, "..."), "_");
+
+        let long = "とても長いライトノベルのタイトル".repeat(10);
+        let name = sanitize_with(&rules, &long);
+        assert!(name.len() <= 200);
+        assert!(long.starts_with(&name));
+
+        let rules = FilenameConfig {
+            replacements: [(":".to_string(), " - ".to_string())].into(),
+            replacement: String::new(),
+            max_bytes: 10,
+        };
+        assert_eq!(sanitize_with(&rules, "a:b/c"), "a - bc");
+        assert_eq!(sanitize_with(&rules, "abcdefgh. xyz"), "abcdefgh");
+    }
+}
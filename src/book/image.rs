@@ -0,0 +1,319 @@
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use anyhow::Result;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+
+use crate::book::avif;
+use crate::config::DeviceProfile;
+
+/// 转换为 JPEG 时使用的质量
+const JPEG_QUALITY: u8 = 90;
+
+/// 插画的实际格式，按文件头或响应的 Content-Type 判断
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Gif,
+    Webp,
+    Avif,
+    Bmp,
+    Svg,
+}
+
+impl ImageFormat {
+    /// 按文件头的魔数判断格式
+    pub fn sniff(content: &[u8]) -> Option<Self> {
+        match content {
+            [0xFF, 0xD8, 0xFF, ..] => Some(ImageFormat::Jpeg),
+            [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some(ImageFormat::Png),
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(ImageFormat::Gif),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => {
+                Some(ImageFormat::Webp)
+            }
+            [_, _, _, _, b'f', b't', b'y', b'p', b'a', b'v', b'i', b'f' | b's', ..] => {
+                Some(ImageFormat::Avif)
+            }
+            [b'B', b'M', ..] => Some(ImageFormat::Bmp),
+            _ => {
+                let head = String::from_utf8_lossy(&content[..content.len().min(1024)]);
+                let head = head.trim_start_matches('\u{feff}').trim_start();
+                (head.starts_with('<') && head.contains("<svg")).then_some(ImageFormat::Svg)
+            }
+        }
+    }
+
+    /// 按 Content-Type 判断格式，忽略 charset 等参数
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type.split(';').next()?.trim().to_ascii_lowercase();
+        match mime.as_str() {
+            "image/jpeg" | "image/jpg" | "image/pjpeg" => Some(ImageFormat::Jpeg),
+            "image/png" => Some(ImageFormat::Png),
+            "image/gif" => Some(ImageFormat::Gif),
+            "image/webp" => Some(ImageFormat::Webp),
+            "image/avif" => Some(ImageFormat::Avif),
+            "image/bmp" | "image/x-ms-bmp" => Some(ImageFormat::Bmp),
+            "image/svg+xml" => Some(ImageFormat::Svg),
+            _ => None,
+        }
+    }
+
+    /// 优先使用文件头判断，无法识别时再使用 Content-Type
+    pub fn detect(content: &[u8], content_type: Option<&str>) -> Option<Self> {
+        Self::sniff(content).or_else(|| content_type.and_then(Self::from_content_type))
+    }
+
+    /// 读取文件头判断已下载文件的格式
+    pub fn sniff_file(path: &Path) -> Result<Option<Self>> {
+        let mut head = Vec::with_capacity(1024);
+        std::fs::File::open(path)?
+            .take(1024)
+            .read_to_end(&mut head)?;
+        Ok(Self::sniff(&head))
+    }
+
+    /// 不属于 epub 核心媒体类型、部分阅读器无法显示的格式
+    pub fn needs_conversion(self) -> bool {
+        matches!(
+            self,
+            ImageFormat::Webp | ImageFormat::Avif | ImageFormat::Bmp
+        )
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Png => "png",
+            ImageFormat::Gif => "gif",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Avif => "avif",
+            ImageFormat::Bmp => "bmp",
+            ImageFormat::Svg => "svg",
+        }
+    }
+}
+
+/// 将图片转换为 JPEG，含有透明像素时转换为 PNG，返回转换后的格式与内容。
+/// 动图只保留第一帧；image 不带 AVIF 解码器，AVIF 由 avif 模块解码
+pub fn convert(content: &[u8]) -> Result<(ImageFormat, Vec<u8>)> {
+    let image = match ImageFormat::sniff(content) {
+        Some(ImageFormat::Avif) => avif::decode(content)?,
+        _ => image::load_from_memory(content)?,
+    };
+    let mut output = Cursor::new(vec![]);
+    let transparent =
+        image.color().has_alpha() && image.to_rgba8().pixels().any(|pixel| pixel[3] < 255);
+    let format = match transparent {
+        true => {
+            image.write_to(&mut output, image::ImageFormat::Png)?;
+            ImageFormat::Png
+        }
+        false => {
+            JpegEncoder::new_with_quality(&mut output, JPEG_QUALITY)
+                .encode_image(&image.to_rgb8())?;
+            ImageFormat::Jpeg
+        }
+    };
+    Ok((format, output.into_inner()))
+}
+
+/// 按设备配置缩放、转换灰度并重新压缩 JPEG 与 PNG，保持原格式。
+/// 其他格式、处理失败或处理后没有变小时返回 None，沿用原图
+pub fn fit_profile(content: &[u8], profile: &DeviceProfile) -> Option<Vec<u8>> {
+    let format = ImageFormat::sniff(content)?;
+    if !matches!(format, ImageFormat::Jpeg | ImageFormat::Png) {
+        return None;
+    }
+    let mut image = image::load_from_memory(content).ok()?;
+    if image.width() > profile.max_width || image.height() > profile.max_height {
+        image = image.resize(profile.max_width, profile.max_height, FilterType::Lanczos3);
+    }
+    if profile.grayscale {
+        image = image.grayscale();
+    }
+    let mut output = Cursor::new(vec![]);
+    match format {
+        ImageFormat::Jpeg => JpegEncoder::new_with_quality(&mut output, profile.jpeg_quality)
+            .encode_image(&image)
+            .ok()?,
+        _ => image.write_to(&mut output, image::ImageFormat::Png).ok()?,
+    }
+    let output = output.into_inner();
+    (output.len() < content.len()).then_some(output)
+}
+
+/// 一本书打包时图片处理前后的总大小
+#[derive(Default, Debug)]
+pub struct SizeReport {
+    pub images: usize,
+    pub original: u64,
+    pub processed: u64,
+}
+
+impl SizeReport {
+    pub fn add(&mut self, original: u64, processed: u64) {
+        self.images += 1;
+        self.original += original;
+        self.processed += processed;
+    }
+
+    pub fn saved(&self) -> u64 {
+        self.original.saturating_sub(self.processed)
+    }
+}
+
+impl std::fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const MB: f64 = 1024.0 * 1024.0;
+        let percent = match self.original {
+            0 => 0.0,
+            original => self.saved() as f64 * 100.0 / original as f64,
+        };
+        write!(
+            f,
+            "{}张图片 {:.2}MB -> {:.2}MB，节省 {:.2}MB（{:.1}%）",
+            self.images,
+            self.original as f64 / MB,
+            self.processed as f64 / MB,
+            self.saved() as f64 / MB,
+            percent
+        )
+    }
+}
+
+/// 将文件名的扩展名替换为实际格式对应的扩展名
+pub fn with_extension(name: &str, format: ImageFormat) -> String {
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    format!("{}.{}", stem, format.extension())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::opf::media_type;
+
+    #[test]
+    fn test_sniff() {
+        let cases: [(&[u8], _); 8] = [
+            (b"\xFF\xD8\xFF\xE0\x00\x10JFIF", Some(ImageFormat::Jpeg)),
+            (b"\x89PNG\r\n\x1a\n\x00\x00", Some(ImageFormat::Png)),
+            (b"GIF89a\x01\x00", Some(ImageFormat::Gif)),
+            (b"RIFF\x24\x00\x00\x00WEBPVP8 ", Some(ImageFormat::Webp)),
+            (b"\x00\x00\x00\x1cftypavif\x00", Some(ImageFormat::Avif)),
+            (b"BM\x36\x00\x00\x00", Some(ImageFormat::Bmp)),
+            (
+                b"<?xml version=\"1.0\"?>\n<svg xmlns=\"\">",
+                Some(ImageFormat::Svg),
+            ),
+            (b"<html>404</html>", None),
+        ];
+        for (content, format) in cases {
+            assert_eq!(ImageFormat::sniff(content), format);
+        }
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            ImageFormat::detect(b"\x89PNG\r\n\x1a\n", Some("image/jpeg")),
+            Some(ImageFormat::Png)
+        );
+        assert_eq!(
+            ImageFormat::detect(b"????", Some("Image/WebP; charset=binary")),
+            Some(ImageFormat::Webp)
+        );
+        assert_eq!(ImageFormat::detect(b"????", Some("text/html")), None);
+        assert_eq!(with_extension("abc.jpg", ImageFormat::Png), "abc.png");
+        assert_eq!(with_extension("cover", ImageFormat::Gif), "cover.gif");
+        for format in [
+            ImageFormat::Jpeg,
+            ImageFormat::Png,
+            ImageFormat::Gif,
+            ImageFormat::Webp,
+            ImageFormat::Avif,
+            ImageFormat::Bmp,
+            ImageFormat::Svg,
+        ] {
+            assert_eq!(
+                ImageFormat::from_content_type(media_type(&with_extension("a", format))),
+                Some(format)
+            );
+        }
+    }
+
+    #[test]
+    fn test_convert() -> Result<()> {
+        let sample = |name: &str| std::fs::read(Path::new("tests/images").join(name));
+        for (name, expected) in [
+            ("sample.webp", ImageFormat::Jpeg),
+            ("sample.avif", ImageFormat::Jpeg),
+            ("sample.bmp", ImageFormat::Jpeg),
+            ("sample_alpha.webp", ImageFormat::Png),
+            ("sample_alpha.avif", ImageFormat::Png),
+        ] {
+            let content = sample(name)?;
+            assert!(ImageFormat::sniff(&content).is_some_and(ImageFormat::needs_conversion));
+            let (format, converted) = convert(&content)?;
+            assert_eq!(format, expected, "{}", name);
+            assert_eq!(ImageFormat::sniff(&converted), Some(expected));
+            let image = image::load_from_memory(&converted)?;
+            assert_eq!((image.width(), image.height()), (16, 12));
+        }
+        assert!(!ImageFormat::Gif.needs_conversion());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fit_profile() -> Result<()> {
+        let photo = image::RgbImage::from_fn(400, 300, |x, y| {
+            image::Rgb([(x % 256) as u8, (y % 256) as u8, ((x * y) % 256) as u8])
+        });
+        let mut jpeg = Cursor::new(vec![]);
+        JpegEncoder::new_with_quality(&mut jpeg, 100).encode_image(&photo)?;
+        let jpeg = jpeg.into_inner();
+        let profile = DeviceProfile {
+            max_width: 200,
+            max_height: 200,
+            jpeg_quality: 60,
+            grayscale: true,
+        };
+        let fitted = fit_profile(&jpeg, &profile).unwrap();
+        assert!(fitted.len() < jpeg.len());
+        assert_eq!(ImageFormat::sniff(&fitted), Some(ImageFormat::Jpeg));
+        let image = image::load_from_memory(&fitted)?;
+        assert_eq!((image.width(), image.height()), (200, 150));
+        assert!(image
+            .to_rgb8()
+            .pixels()
+            .all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2]));
+
+        let mut png = Cursor::new(vec![]);
+        photo.write_to(&mut png, image::ImageFormat::Png)?;
+        let fitted = fit_profile(png.get_ref(), &profile).unwrap();
+        assert_eq!(ImageFormat::sniff(&fitted), Some(ImageFormat::Png));
+        assert_eq!(image::load_from_memory(&fitted)?.width(), 200);
+
+        // 其他格式与处理后没有变小的图片沿用原图
+        let webp = std::fs::read("tests/images/sample.webp")?;
+        assert!(fit_profile(&webp, &profile).is_none());
+        let small = fit_profile(&jpeg, &profile).unwrap();
+        let unchanged = DeviceProfile {
+            max_width: 1000,
+            max_height: 1000,
+            jpeg_quality: 100,
+            grayscale: false,
+        };
+        assert!(fit_profile(&small, &unchanged).is_none());
+
+        let mut report = SizeReport::default();
+        report.add(4 * 1024 * 1024, 1024 * 1024);
+        assert_eq!(report.saved(), 3 * 1024 * 1024);
+        assert_eq!(
+            report.to_string(),
+            "1张图片 4.00MB -> 1.00MB，节省 3.00MB（75.0%）"
+        );
+        Ok(())
+    }
+}
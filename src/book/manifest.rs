@@ -0,0 +1,238 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+
+use super::Episode;
+use crate::fs::atomic_write;
+use crate::source::BookInfo;
+
+/// 已下载章节的记录，用于增量更新时比对远端目录
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChapterRecord {
+    pub url: String,
+    pub title: String,
+    /// 章节正文的 md5
+    pub hash: String,
+}
+
+/// 保存在小说目录下的 `manifest.json`，记录上次生成时的章节与插画
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Manifest {
+    pub url: String,
+    pub title: String,
+    pub author: String,
+    pub chapters: Vec<ChapterRecord>,
+    pub illustrations: HashMap<String, String>,
+    /// 远端目录中的全部卷名，用于分卷输出时确定卷号
+    #[serde(default)]
+    pub volumes: Vec<String>,
+    /// 站点名称
+    #[serde(default)]
+    pub source: String,
+    /// 站点的显示名称
+    #[serde(default)]
+    pub publisher: String,
+    /// 站点显示的最后更新日期
+    #[serde(default)]
+    pub updated: Option<String>,
+    /// 详情页中的类型、标签、字数与简介
+    #[serde(default)]
+    pub info: BookInfo,
+}
+
+impl Manifest {
+    pub const FILE_NAME: &'static str = "manifest.json";
+    pub const CHAPTER_DIR: &'static str = "chapters";
+
+    pub async fn load(save_path: &Path) -> Result<Self> {
+        let content = tokio::fs::read_to_string(save_path.join(Manifest::FILE_NAME)).await?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub async fn save(&self, save_path: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+        atomic_write(&save_path.join(Manifest::FILE_NAME), content.as_bytes()).await
+    }
+
+    /// 将上次的记录合并到本次的记录中，并按远端目录 `chapter_urls` 排序：
+    /// 本次没有记录的章节沿用上次的记录，远端目录中已不存在的章节不再保留
+    pub fn merge_chapters(&mut self, previous: Vec<ChapterRecord>, chapter_urls: &[String]) {
+        let mut records: HashMap<_, _> = previous
+            .into_iter()
+            .chain(std::mem::take(&mut self.chapters))
+            .map(|record| (record.url.clone(), record))
+            .collect();
+        self.chapters = chapter_urls
+            .iter()
+            .filter_map(|url| records.remove(url))
+            .collect();
+    }
+
+    /// 删除远端目录 `chapter_urls` 中已不存在的章节缓存
+    pub async fn prune_chapters(save_path: &Path, chapter_urls: &[String]) -> Result<()> {
+        let remote: HashSet<_> = chapter_urls
+            .iter()
+            .map(|url| format!("{}.json", hash(url)))
+            .collect();
+        let Ok(mut entries) = tokio::fs::read_dir(save_path.join(Manifest::CHAPTER_DIR)).await
+        else {
+            return Ok(());
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            if !remote.contains(entry.file_name().to_string_lossy().as_ref()) {
+                tokio::fs::remove_file(entry.path()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 远端目录中的章节在本地相同标题的记录
+    pub fn record(&self, url: &str, title: &str) -> Option<&ChapterRecord> {
+        self.chapters
+            .iter()
+            .find(|chapter| chapter.url == url && chapter.title == title)
+    }
+
+    /// 章节的标题与正文是否都和本地记录相同
+    pub fn is_unchanged(&self, episode: &Episode) -> bool {
+        self.record(&episode.url, &episode.episode_title)
+            .is_some_and(|record| record.hash == hash(&episode.content))
+    }
+
+    fn chapter_path(save_path: &Path, url: &str) -> std::path::PathBuf {
+        save_path
+            .join(Manifest::CHAPTER_DIR)
+            .join(format!("{}.json", hash(url)))
+    }
+
+    /// 读取缓存的章节，并按当前目录中的位置重新编号
+    pub async fn load_episode(save_path: &Path, url: &str, order: u32) -> Result<Episode> {
+        let content = tokio::fs::read_to_string(Manifest::chapter_path(save_path, url)).await?;
+        let mut episode: Episode = serde_json::from_str(&content)?;
+        episode.order = order;
+        episode.episode_save_path = format!("Text/{}.xhtml", order);
+        Ok(episode)
+    }
+
+    /// 读取记录中的章节，正文的 md5 与记录不一致时返回错误，
+    /// 例如记录之后中断的下载重新获取了该章节
+    pub async fn load_recorded(
+        save_path: &Path,
+        record: &ChapterRecord,
+        order: u32,
+    ) -> Result<Episode> {
+        let episode = Manifest::load_episode(save_path, &record.url, order).await?;
+        if hash(&episode.content) != record.hash {
+            bail!("正文与本地记录不一致");
+        }
+        Ok(episode)
+    }
+
+    pub async fn save_episode(save_path: &Path, episode: &Episode) -> Result<()> {
+        let content = serde_json::to_string(episode)?;
+        atomic_write(
+            &Manifest::chapter_path(save_path, &episode.url),
+            content.as_bytes(),
+        )
+        .await
+    }
+}
+
+pub fn hash(content: &str) -> String {
+    let mut hasher = Md5::new();
+    hasher.update(content);
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_manifest_round_trip() -> Result<()> {
+        let save_path = std::env::temp_dir().join("ranobe-manifest-test");
+        tokio::fs::create_dir_all(&save_path).await?;
+        let episode = Episode {
+            episode_title: "第一章".to_string(),
+            content: "<p>正文</p>".to_string(),
+            episode_save_path: "Text/1.xhtml".to_string(),
+            order: 1,
+            url: "https://www.esjzone.me/forum/1/1.html".to_string(),
+            volume: None,
+        };
+        let manifest = Manifest {
+            url: "https://www.esjzone.me/detail/1.html".to_string(),
+            title: "下北泽秘闻".to_string(),
+            author: "野兽先生".to_string(),
+            chapters: vec![ChapterRecord {
+                url: episode.url.clone(),
+                title: episode.episode_title.clone(),
+                hash: hash(&episode.content),
+            }],
+            illustrations: HashMap::new(),
+            volumes: vec![],
+            source: "esjzone".to_string(),
+            publisher: "ESJ Zone".to_string(),
+            updated: None,
+            info: BookInfo::default(),
+        };
+        let removed = Episode {
+            url: "https://www.esjzone.me/forum/1/2.html".to_string(),
+            ..episode.clone()
+        };
+        let unselected = ChapterRecord {
+            url: "https://www.esjzone.me/forum/1/3.html".to_string(),
+            title: "第三章".to_string(),
+            hash: hash(&episode.content),
+        };
+        let mut manifest = manifest;
+        let chapter_urls = vec![unselected.url.clone(), episode.url.clone()];
+        manifest.merge_chapters(
+            vec![unselected.clone(), manifest.chapters[0].clone()],
+            &chapter_urls,
+        );
+        // 本次没有获取的章节沿用上次的记录，按远端目录排序
+        let urls: Vec<_> = manifest.chapters.iter().map(|record| &record.url).collect();
+        assert_eq!(urls, [&unselected.url, &episode.url]);
+        Manifest::save_episode(&save_path, &episode).await?;
+        Manifest::save_episode(&save_path, &removed).await?;
+        manifest.save(&save_path).await?;
+        Manifest::prune_chapters(&save_path, &chapter_urls).await?;
+        // 只删除远端目录中已不存在的章节
+        assert!(Manifest::load_episode(&save_path, &removed.url, 2)
+            .await
+            .is_err());
+        assert!(Manifest::load_episode(&save_path, &episode.url, 1)
+            .await
+            .is_ok());
+
+        let manifest = Manifest::load(&save_path).await?;
+        assert!(manifest.is_unchanged(&episode));
+        assert!(manifest.record(&episode.url, "第一章（修订）").is_none());
+        let record = manifest.record(&episode.url, "第一章").unwrap();
+        assert_eq!(
+            Manifest::load_recorded(&save_path, record, 1)
+                .await?
+                .content,
+            episode.content
+        );
+        let cached = Manifest::load_episode(&save_path, &episode.url, 3).await?;
+        assert_eq!(cached.content, episode.content);
+        assert_eq!(cached.order, 3);
+        assert_eq!(cached.episode_save_path, "Text/3.xhtml");
+        let edited = Episode {
+            content: "<p>修订后的正文</p>".to_string(),
+            ..episode.clone()
+        };
+        assert!(!manifest.is_unchanged(&edited));
+        Manifest::save_episode(&save_path, &edited).await?;
+        assert!(Manifest::load_recorded(&save_path, record, 1)
+            .await
+            .is_err());
+        tokio::fs::remove_dir_all(&save_path).await?;
+        Ok(())
+    }
+}
@@ -1,8 +1,30 @@
+mod avif;
+#[allow(clippy::module_inception)]
 mod book;
+mod cache;
 mod episode;
+mod epub;
+mod filename;
+mod image;
+mod manifest;
+mod nav;
 mod opf;
+mod output;
+mod report;
+mod sanitize;
+mod selection;
 mod toc;
+mod volume;
 
-pub use crate::book::book::Book;
-pub use crate::book::episode::Episode;
+pub use crate::book::book::{Book, DownloadOptions};
+pub use crate::book::cache::Cache;
+pub use crate::book::episode::{xhtml, Episode};
+pub use crate::book::filename::safe_file_name;
+pub use crate::book::manifest::{ChapterRecord, Manifest};
+pub use crate::book::nav::Nav;
 pub use crate::book::opf::Opf;
+pub use crate::book::output::{OutputFiles, OutputTemplate};
+pub use crate::book::report::{Failure, FailureReport};
+pub use crate::book::sanitize::{image_sources, sanitize};
+pub use crate::book::selection::ChapterSelection;
+pub use crate::book::volume::Volume;
@@ -0,0 +1,155 @@
+use anyhow::Result;
+use quick_xml::se::to_string;
+use serde::Serialize;
+
+use super::*;
+
+/// EPUB 3 的导航文档 `nav.xhtml`
+#[derive(Serialize)]
+#[serde(rename = "html")]
+pub struct Nav {
+    #[serde(skip)]
+    prefix: String,
+    #[serde(rename = "@xmlns")]
+    xmlns: String,
+    #[serde(rename = "@xmlns:epub")]
+    xmlns_epub: String,
+    #[serde(rename = "@xml:lang")]
+    xml_lang: String,
+    #[serde(rename = "@lang")]
+    lang: String,
+    head: Head,
+    body: Body,
+}
+
+#[derive(Serialize)]
+pub struct Head {
+    title: String,
+}
+
+#[derive(Serialize)]
+pub struct Body {
+    nav: NavElement,
+}
+
+#[derive(Serialize)]
+pub struct NavElement {
+    #[serde(rename = "@epub:type")]
+    epub_type: String,
+    #[serde(rename = "@id")]
+    id: String,
+    h1: String,
+    ol: Ol,
+}
+
+#[derive(Serialize)]
+pub struct Ol {
+    li: Vec<Li>,
+}
+
+#[derive(Serialize)]
+pub struct Li {
+    a: Link,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ol: Option<Ol>,
+}
+
+#[derive(Serialize)]
+pub struct Link {
+    #[serde(rename = "@href")]
+    href: String,
+    #[serde(rename = "$text")]
+    text: String,
+}
+
+impl Li {
+    fn new(text: &str, href: &str) -> Self {
+        Li {
+            a: Link {
+                href: href.to_string(),
+                text: text.to_string(),
+            },
+            ol: None,
+        }
+    }
+}
+
+impl Nav {
+    /// 与 `toc.ncx` 相同的目录结构，分卷的章节嵌套在卷之下
+    pub fn new(title: &str, episodes: &[Episode], about: bool) -> Self {
+        let mut items = vec![Li::new("封面", "Text/titlepage.xhtml")];
+        if about {
+            items.push(Li::new("关于本书", "Text/about.xhtml"));
+        }
+        for volume in Volume::group(episodes) {
+            let chapters = volume
+                .episodes
+                .iter()
+                .map(|episode| Li::new(&episode.episode_title, &episode.episode_save_path));
+            match volume.title {
+                Some(volume_title) => {
+                    let mut item = Li::new(volume_title, &volume.episodes[0].episode_save_path);
+                    item.ol = Some(Ol {
+                        li: chapters.collect(),
+                    });
+                    items.push(item);
+                }
+                None => items.extend(chapters),
+            }
+        }
+        Nav {
+            prefix: "<?xml version=\"1.0\" encoding=\"utf-8\"?><!DOCTYPE html>".to_string(),
+            xmlns: "http://www.w3.org/1999/xhtml".to_string(),
+            xmlns_epub: "http://www.idpf.org/2007/ops".to_string(),
+            xml_lang: "zh-CN".to_string(),
+            lang: "zh-CN".to_string(),
+            head: Head {
+                title: title.to_string(),
+            },
+            body: Body {
+                nav: NavElement {
+                    epub_type: "toc".to_string(),
+                    id: "toc".to_string(),
+                    h1: "目录".to_string(),
+                    ol: Ol { li: items },
+                },
+            },
+        }
+    }
+
+    pub fn content(&self) -> Result<String> {
+        let suffix = to_string(&self)?;
+        Ok(format!("{}{}", &self.prefix, &suffix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nav() -> Result<()> {
+        let episode = |order: u32, title: &str, volume: Option<&str>| Episode {
+            episode_title: title.to_string(),
+            content: String::new(),
+            episode_save_path: format!("Text/{}.xhtml", order),
+            order,
+            url: format!("https://mock.test/{}.html", order),
+            volume: volume.map(str::to_string),
+        };
+        let episodes = vec![
+            episode(1, "简介 <1>", None),
+            episode(2, "第一章", Some("第一卷")),
+            episode(3, "第二章", Some("第一卷")),
+        ];
+        let nav = Nav::new("下北泽秘闻", &episodes, false).content()?;
+        assert!(nav.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?><!DOCTYPE html><html xmlns=\"http://www.w3.org/1999/xhtml\""));
+        assert!(nav.contains(r#"<nav epub:type="toc" id="toc"><h1>目录</h1><ol><li><a href="Text/titlepage.xhtml">封面</a></li>"#));
+        assert!(nav.contains(r#"<li><a href="Text/1.xhtml">简介 &lt;1&gt;</a></li>"#));
+        assert!(nav.contains(r#"<li><a href="Text/2.xhtml">第一卷</a><ol><li><a href="Text/2.xhtml">第一章</a></li><li><a href="Text/3.xhtml">第二章</a></li></ol></li>"#));
+
+        let nav = Nav::new("下北泽秘闻", &episodes, true).content()?;
+        assert!(nav.contains(r#"<li><a href="Text/titlepage.xhtml">封面</a></li><li><a href="Text/about.xhtml">关于本书</a></li><li><a href="Text/1.xhtml">"#));
+        Ok(())
+    }
+}
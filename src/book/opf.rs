@@ -2,11 +2,11 @@ use std::vec;
 
 use anyhow::Result;
 use quick_xml::se::to_string;
-use serde;
 use serde::{Deserialize, Serialize};
 
-
+use super::book::is_cover;
 use super::Book;
+use crate::config::OutputFormat;
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -20,10 +20,11 @@ pub struct Opf {
     version: String,
     #[serde(rename = "@unique-identifier")]
     unique_identifier: String,
-    meta_data: MetaData,
+    metadata: MetaData,
     manifest: Manifest,
     spine: Spine,
-    guide: Guide,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    guide: Option<Guide>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -39,20 +40,52 @@ pub struct MetaData {
     xmlns_xsi: String,
     #[serde(rename = "@xmlns:calibre")]
     xmlns_calibre: String,
+    #[serde(rename = "dc:identifier")]
+    dc_identifier: Identifier,
     #[serde(rename = "dc:title")]
     dc_title: String,
     #[serde(rename = "dc:creator")]
     dc_creator: String,
-    meta: Meta,
+    #[serde(rename = "dc:language")]
+    dc_language: String,
+    #[serde(rename = "dc:publisher", skip_serializing_if = "Option::is_none")]
+    dc_publisher: Option<String>,
+    #[serde(rename = "dc:source", skip_serializing_if = "Option::is_none")]
+    dc_source: Option<String>,
+    #[serde(rename = "dc:date", skip_serializing_if = "Option::is_none")]
+    dc_date: Option<String>,
+    #[serde(rename = "dc:description", skip_serializing_if = "Option::is_none")]
+    dc_description: Option<String>,
+    /// 类型、标签与连载状态，连载状态也作为标签写入，便于在书库中筛选
+    #[serde(rename = "dc:subject", default, skip_serializing_if = "Vec::is_empty")]
+    dc_subject: Vec<String>,
+    meta: Vec<Meta>,
 }
 
 #[derive(Serialize, Deserialize)]
+pub struct Identifier {
+    #[serde(rename = "@id")]
+    id: String,
+    #[serde(rename = "$text")]
+    value: String,
+}
+
+/// OPF 2.0 的 `name`/`content` 形式与 OPF 3.0 的 `property` 形式共用
+#[derive(Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct Meta {
-    #[serde(rename = "@name")]
-    name: String,
-    #[serde(rename = "@content")]
-    content: String,
+    #[serde(rename = "@name", skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(rename = "@content", skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+    #[serde(rename = "@id", skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(rename = "@refines", skip_serializing_if = "Option::is_none")]
+    refines: Option<String>,
+    #[serde(rename = "@property", skip_serializing_if = "Option::is_none")]
+    property: Option<String>,
+    #[serde(rename = "$text", skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -70,6 +103,8 @@ pub struct Item {
     href: String,
     #[serde(rename = "@media-type")]
     media_type: String,
+    #[serde(rename = "@properties", skip_serializing_if = "Option::is_none")]
+    properties: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -104,83 +139,326 @@ pub struct Reference {
     title: String,
 }
 
+impl Meta {
+    fn named(name: &str, content: &str) -> Self {
+        Meta {
+            name: Some(name.to_string()),
+            content: Some(content.to_string()),
+            ..Meta::default()
+        }
+    }
+
+    fn property(property: &str, value: &str) -> Self {
+        Meta {
+            property: Some(property.to_string()),
+            value: Some(value.to_string()),
+            ..Meta::default()
+        }
+    }
+}
+
+impl Item {
+    fn new(id: &str, href: &str, media_type: &str) -> Self {
+        Item {
+            id: id.to_string(),
+            href: href.to_string(),
+            media_type: media_type.to_string(),
+            properties: None,
+        }
+    }
+}
+
+/// 将站点显示的日期转换为 `dc:date` 要求的 `YYYY-MM-DD` 形式，无法识别时返回 None
+fn w3c_date(date: &str) -> Option<String> {
+    ["%Y-%m-%d", "%Y/%m/%d", "%Y.%m.%d", "%Y年%m月%d日"]
+        .iter()
+        .find_map(|format| chrono::NaiveDate::parse_from_str(date.trim(), format).ok())
+        .map(|date| date.format("%Y-%m-%d").to_string())
+}
+
+/// 按扩展名推断 manifest 中的 media-type
+pub fn media_type(name: &str) -> &'static str {
+    let extension = name
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("bmp") => "image/bmp",
+        Some("svg") => "image/svg+xml",
+        Some("xhtml") => "application/xhtml+xml",
+        Some("css") => "text/css",
+        _ => "image/jpeg",
+    }
+}
+
 impl Opf {
     pub fn new(book: &Book) -> Self {
+        let epub3 = book.format == OutputFormat::Epub3;
         let meta_data = MetaData {
             xmlns_opf: "http://www.idpf.org/2007/opf".to_string(),
             xmlns_dc: "http://purl.org/dc/elements/1.1/".to_string(),
             xmlns_dcterms: "http://purl.org/dc/terms/".to_string(),
             xmlns_xsi: "http://www.w3.org/2001/XMLSchema-instance".to_string(),
             xmlns_calibre: "http://calibre.kovidgoyal.net/2009/metadata".to_string(),
+            dc_identifier: Identifier {
+                id: "uuid_id".to_string(),
+                value: book.identifier(),
+            },
             dc_title: book.title.clone(),
             dc_creator: book.author.clone(),
-            meta: Meta {
-                name: "cover".to_string(),
-                content: "cover.jpg".to_string(),
-            },
+            dc_language: "zh-CN".to_string(),
+            dc_publisher: Some(book.publisher.clone()).filter(|publisher| !publisher.is_empty()),
+            dc_source: Some(book.url.clone()).filter(|url| !url.is_empty()),
+            dc_date: book.updated.as_deref().and_then(w3c_date),
+            dc_description: book.info.synopsis.clone(),
+            dc_subject: book
+                .info
+                .kind
+                .iter()
+                .chain(&book.info.tags)
+                .chain(&book.info.status)
+                .cloned()
+                .collect(),
+            meta: Opf::meta(book),
         };
         let mut item = vec![];
         let mut itemref = vec![];
-        item.push(Item {
-            id: "titlepage.xhtml".to_string(),
-            href: "Text/titlepage.xhtml".to_string(),
-            media_type: "application/xhtml+xml".to_string(),
-        });
+        item.push(Item::new(
+            "titlepage.xhtml",
+            "Text/titlepage.xhtml",
+            "application/xhtml+xml",
+        ));
         itemref.push(Itemref {
             idref: "titlepage.xhtml".to_string(),
         });
-        book.episodes.iter().for_each(|episode| {
-            item.push(Item {
-                id: format!("{}.xhtml", &episode.order),
-                href: episode.episode_save_path.clone(),
-                media_type: "application/xhtml+xml".to_string(),
+        if book.about_page {
+            item.push(Item::new(
+                "about.xhtml",
+                "Text/about.xhtml",
+                "application/xhtml+xml",
+            ));
+            itemref.push(Itemref {
+                idref: "about.xhtml".to_string(),
             });
+        }
+        book.episodes.iter().for_each(|episode| {
+            item.push(Item::new(
+                &format!("ep{}", &episode.order),
+                &episode.episode_save_path,
+                "application/xhtml+xml",
+            ));
             itemref.push(Itemref {
-                idref: format!("{}.xhtml", &episode.order),
+                idref: format!("ep{}", &episode.order),
             })
         });
-        book.illustration_urls
-            .values()
+        let cover_name = book.cover_name();
+        let mut cover = Item::new(
+            &cover_name,
+            &format!("Images/{}", cover_name),
+            media_type(&cover_name),
+        );
+        if epub3 {
+            cover.properties = Some("cover-image".to_string());
+        }
+        item.push(cover);
+        book.illustration_files()
+            .into_iter()
+            .filter(|name| !is_cover(name))
             .enumerate()
             .for_each(|(idx, name)| {
-                item.push(Item {
-                    id: format!("added{}", idx as u32),
-                    href: format!("Images/{}", name),
-                    media_type: "image/jpeg".to_string(),
-                })
+                item.push(Item::new(
+                    &format!("added{}", idx as u32),
+                    &format!("Images/{}", name),
+                    media_type(name),
+                ))
             });
-        item.push(Item {
-            id: "ncx".to_string(),
-            href: "toc.ncx".to_string(),
-            media_type: "application/x-dtbncx+xml".to_string(),
-        });
+        item.push(Item::new("ncx", "toc.ncx", "application/x-dtbncx+xml"));
+        if epub3 {
+            let mut nav = Item::new("nav", "nav.xhtml", "application/xhtml+xml");
+            nav.properties = Some("nav".to_string());
+            item.push(nav);
+        }
         let manifest = Manifest { item };
         let spine = Spine {
             toc: "ncx".to_string(),
             itemref,
         };
-        let guide = Guide {
+        // guide 在 EPUB 3 中已废弃，仅在 EPUB 2 中输出
+        let guide = (!epub3).then(|| Guide {
             reference: Reference {
                 r#type: "cover".to_string(),
-                href: "titlepage.xhtml".to_string(),
+                href: "Text/titlepage.xhtml".to_string(),
                 title: "Cover".to_string(),
             },
-        };
+        });
         Opf {
             prefix: "<?xml version=\"1.0\" encoding=\"UTF-8\"?>".to_string(),
             xmlns: "http://www.idpf.org/2007/opf".to_string(),
-            version: "2.0".to_string(),
+            version: match epub3 {
+                true => "3.0",
+                false => "2.0",
+            }
+            .to_string(),
             unique_identifier: "uuid_id".to_string(),
-            meta_data,
+            metadata: meta_data,
             manifest,
             spine,
             guide,
         }
     }
 
+    /// 封面、修改时间与分卷输出时的系列信息
+    fn meta(book: &Book) -> Vec<Meta> {
+        let mut meta = vec![Meta::named("cover", &book.cover_name())];
+        if book.format == OutputFormat::Epub3 {
+            meta.push(Meta::property(
+                "dcterms:modified",
+                &chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            ));
+        }
+        if let Some(series) = &book.series {
+            meta.push(Meta::named("calibre:series", &series.name));
+            meta.push(Meta::named(
+                "calibre:series_index",
+                &series.index.to_string(),
+            ));
+            if book.format == OutputFormat::Epub3 {
+                meta.push(Meta {
+                    id: Some("series".to_string()),
+                    ..Meta::property("belongs-to-collection", &series.name)
+                });
+                meta.push(Meta {
+                    refines: Some("#series".to_string()),
+                    ..Meta::property("collection-type", "series")
+                });
+                meta.push(Meta {
+                    refines: Some("#series".to_string()),
+                    ..Meta::property("group-position", &series.index.to_string())
+                });
+            }
+        }
+        meta
+    }
+
     pub fn content(&self) -> Result<String> {
         let suffix = to_string(&self)?;
         let content = format!("{}{}", &self.prefix, &suffix);
         Ok(content)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::book::Series;
+    use crate::book::Episode;
+    use crate::source::BookInfo;
+
+    fn book(format: OutputFormat) -> Book {
+        let mut book = Book::new();
+        book.url = "https://www.esjzone.me/detail/1.html".to_string();
+        book.title = "下北泽 & 秘闻".to_string();
+        book.author = "野兽先生".to_string();
+        book.format = format;
+        book.episodes = vec![Episode {
+            episode_title: "第一章".to_string(),
+            content: "<p>正文</p>".to_string(),
+            episode_save_path: "Text/1.xhtml".to_string(),
+            order: 1,
+            url: "https://www.esjzone.me/forum/1/1.html".to_string(),
+            volume: None,
+        }];
+        book.illustration_urls = [
+            (
+                "https://mock.test/cover".to_string(),
+                "cover.jpg".to_string(),
+            ),
+            ("https://mock.test/a".to_string(), "a.png".to_string()),
+        ]
+        .into();
+        book.series = Some(Series {
+            name: "下北泽秘闻".to_string(),
+            index: 2,
+        });
+        book.source = "esjzone".to_string();
+        book.publisher = "ESJ Zone".to_string();
+        book.updated = Some("2024/5/30".to_string());
+        book.info = BookInfo {
+            kind: Some("日本輕小說".to_string()),
+            status: Some("連載中".to_string()),
+            tags: vec!["戀愛".to_string(), "校園".to_string()],
+            word_count: Some(114514),
+            synopsis: Some("第一段 <简介>\n第二段".to_string()),
+        };
+        book
+    }
+
+    #[test]
+    fn test_media_type() {
+        assert_eq!(media_type("a.PNG"), "image/png");
+        assert_eq!(media_type("a.jpeg"), "image/jpeg");
+        assert_eq!(media_type("a.webp"), "image/webp");
+        assert_eq!(media_type("Text/1.xhtml"), "application/xhtml+xml");
+    }
+
+    #[test]
+    fn test_epub2() -> Result<()> {
+        let book = book(OutputFormat::Epub2);
+        let opf = Opf::new(&book).content()?;
+        assert!(opf.contains(r#"<package xmlns="http://www.idpf.org/2007/opf" version="2.0" unique-identifier="uuid_id">"#));
+        assert!(opf.contains(&format!(
+            r#"<dc:identifier id="uuid_id">{}</dc:identifier>"#,
+            book.identifier()
+        )));
+        assert!(opf.contains("<dc:title>下北泽 &amp; 秘闻</dc:title>"));
+        assert!(opf.contains("<dc:language>zh-CN</dc:language>"));
+        assert!(opf.contains(r#"<meta name="cover" content="cover.jpg"/>"#));
+        assert!(opf.contains(r#"<item id="added0" href="Images/a.png" media-type="image/png"/>"#));
+        assert!(opf.contains(
+            r#"<item id="ep1" href="Text/1.xhtml" media-type="application/xhtml+xml"/>"#
+        ));
+        assert!(opf.contains(r#"<itemref idref="ep1"/>"#));
+        assert!(opf.contains(r#"<reference type="cover" href="Text/titlepage.xhtml""#));
+        assert!(!opf.contains("nav.xhtml"));
+        assert!(!opf.contains("dcterms:modified"));
+        assert!(opf.contains("<dc:language>zh-CN</dc:language><dc:publisher>ESJ Zone</dc:publisher><dc:source>https://www.esjzone.me/detail/1.html</dc:source><dc:date>2024-05-30</dc:date>"));
+        assert!(opf.contains("<dc:description>第一段 &lt;简介&gt;\n第二段</dc:description>"));
+        assert!(opf.contains("<dc:subject>日本輕小說</dc:subject><dc:subject>戀愛</dc:subject><dc:subject>校園</dc:subject><dc:subject>連載中</dc:subject>"));
+        assert!(!opf.contains("about.xhtml"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_about_page() -> Result<()> {
+        let mut book = book(OutputFormat::Epub3);
+        book.about_page = true;
+        book.updated = Some("最近".to_string());
+        book.info = BookInfo::default();
+        let opf = Opf::new(&book).content()?;
+        assert!(opf.contains(r#"<itemref idref="titlepage.xhtml"/><itemref idref="about.xhtml"/><itemref idref="ep1"/>"#));
+        assert!(opf.contains(
+            r#"<item id="about.xhtml" href="Text/about.xhtml" media-type="application/xhtml+xml"/>"#
+        ));
+        assert!(!opf.contains("dc:date"));
+        assert!(!opf.contains("dc:description"));
+        assert!(!opf.contains("dc:subject"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_epub3() -> Result<()> {
+        let book = book(OutputFormat::Epub3);
+        let opf = Opf::new(&book).content()?;
+        assert!(opf.contains(r#"version="3.0""#));
+        assert!(opf.contains(r#"<meta property="dcterms:modified">"#));
+        assert!(opf.contains(r#"<item id="cover.jpg" href="Images/cover.jpg" media-type="image/jpeg" properties="cover-image"/>"#));
+        assert!(opf.contains(r#"<item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>"#));
+        assert!(opf.contains(r##"<meta refines="#series" property="group-position">2</meta>"##));
+        assert!(!opf.contains("<guide>"));
+        assert_eq!(opf.matches("Images/cover.jpg").count(), 1);
+        Ok(())
+    }
+}
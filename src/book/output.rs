@@ -0,0 +1,236 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Result};
+use tracing::warn;
+
+use super::filename::truncate;
+
+/// 输出文件名中为 `OutputFiles::claim` 添加的 ` (N)` 与写入时的 `.epub.tmp` 预留的字节数
+const RESERVED_BYTES: usize = " (99)".len() + ".epub.tmp".len();
+
+/// 输出文件名模板中可用的字段
+pub const FIELDS: &[&str] = &[
+    "title", "series", "volume", "author", "source", "chapters", "updated",
+];
+
+enum Segment {
+    Text(String),
+    Field(String),
+}
+
+/// 输出文件名模板，如 `{author}/{series}/{title} [{source}] ({chapters}ch).epub`，
+/// `/` 分隔的各级目录会自动创建
+pub struct OutputTemplate {
+    components: Vec<Vec<Segment>>,
+}
+
+impl OutputTemplate {
+    pub fn parse(template: &str) -> Result<Self> {
+        let template = template.trim();
+        if template.is_empty() {
+            bail!("输出文件名模板不能为空");
+        }
+        if template.starts_with('/') {
+            bail!("输出文件名模板必须是相对路径");
+        }
+        let components = template
+            .split('/')
+            .map(|component| match component.trim() {
+                ".." => Err(anyhow!("输出文件名模板中不能包含 `..`")),
+                component => OutputTemplate::parse_component(component),
+            })
+            .collect::<Result<_>>()?;
+        Ok(OutputTemplate { components })
+    }
+
+    fn parse_component(component: &str) -> Result<Vec<Segment>> {
+        let mut segments = vec![];
+        let mut rest = component;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| anyhow!("输出文件名模板中的 `{{` 没有闭合: {}", component))?;
+            let field = &rest[start + 1..start + end];
+            if !FIELDS.contains(&field) {
+                bail!(
+                    "输出文件名模板中有未知字段 {{{}}}，可用字段: {}",
+                    field,
+                    FIELDS.join(", ")
+                );
+            }
+            segments.push(Segment::Field(field.to_string()));
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_string()));
+        }
+        Ok(segments)
+    }
+
+    /// 展开为相对于输出目录的路径，字段的值需要事先处理为可用作文件名的字符串；
+    /// 展开后为空的目录被省略，没有 `.epub` 扩展名时自动加上。
+    /// 每级目录名截断到 `max_bytes` 字节，文件名连同序号与临时文件的扩展名也不超过 `max_bytes`
+    pub fn render(&self, fields: &HashMap<&str, String>, max_bytes: usize) -> PathBuf {
+        let mut names: Vec<String> = self
+            .components
+            .iter()
+            .map(|component| {
+                component
+                    .iter()
+                    .map(|segment| match segment {
+                        Segment::Text(text) => text.as_str(),
+                        Segment::Field(field) => {
+                            fields.get(field.as_str()).map_or("", String::as_str)
+                        }
+                    })
+                    .collect::<String>()
+                    .trim()
+                    .to_string()
+            })
+            .filter(|name| !name.is_empty() && name != ".")
+            .collect();
+        let file_name = names.pop().unwrap_or_else(|| {
+            fields
+                .get("title")
+                .map_or("book", String::as_str)
+                .to_string()
+        });
+        let stem = match file_name.strip_suffix(".epub") {
+            Some(stem) if !stem.is_empty() => stem,
+            _ => &file_name,
+        };
+        let mut path: PathBuf = names
+            .iter()
+            .map(|name| truncate(name, max_bytes))
+            .filter(|name| !name.is_empty())
+            .collect();
+        let stem = truncate(stem, max_bytes.saturating_sub(RESERVED_BYTES));
+        path.push(format!(
+            "{}.epub",
+            if stem.is_empty() { "book" } else { stem }
+        ));
+        path
+    }
+}
+
+/// 本次写入的输出文件。目标文件已存在且不属于这本小说，或本次已经写入过时，
+/// 在文件名后加上 ` (2)`、` (3)` 等序号
+#[derive(Default)]
+pub struct OutputFiles {
+    /// 书库中记录的这本小说上次的输出文件，可以直接覆盖
+    owned: HashSet<PathBuf>,
+    pub written: Vec<PathBuf>,
+}
+
+impl OutputFiles {
+    pub fn new(owned: impl IntoIterator<Item = PathBuf>) -> Self {
+        OutputFiles {
+            owned: owned.into_iter().collect(),
+            written: vec![],
+        }
+    }
+
+    /// 确定实际写入的文件并记录
+    pub fn claim(&mut self, path: PathBuf) -> PathBuf {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let claimed = (1..)
+            .map(|idx| match idx {
+                1 => path.clone(),
+                idx => path.with_file_name(format!("{} ({}).epub", stem, idx)),
+            })
+            .find(|candidate| {
+                !self.written.contains(candidate)
+                    && (self.owned.contains(candidate) || !candidate.exists())
+            })
+            .unwrap();
+        if claimed != path {
+            warn!(
+                "{} 已被占用，改为输出到 {}",
+                path.display(),
+                claimed.display()
+            );
+        }
+        self.written.push(claimed.clone());
+        claimed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> HashMap<&'static str, String> {
+        HashMap::from([
+            ("title", "下北泽秘闻 第2卷".to_string()),
+            ("series", "下北泽秘闻".to_string()),
+            ("volume", "2".to_string()),
+            ("author", "野兽先生".to_string()),
+            ("source", "esjzone".to_string()),
+            ("chapters", "114".to_string()),
+            ("updated", String::new()),
+        ])
+    }
+
+    #[test]
+    fn test_render() -> Result<()> {
+        let template =
+            OutputTemplate::parse("{author}/{series}/{title} [{source}] ({chapters}ch).epub")?;
+        assert_eq!(
+            template.render(&fields(), 200),
+            PathBuf::from("野兽先生/下北泽秘闻/下北泽秘闻 第2卷 [esjzone] (114ch).epub")
+        );
+        let template = OutputTemplate::parse("{updated}/{series} {volume}")?;
+        assert_eq!(
+            template.render(&fields(), 200),
+            PathBuf::from("下北泽秘闻 2.epub")
+        );
+
+        assert!(OutputTemplate::parse("{title").is_err());
+        assert!(OutputTemplate::parse("{name}.epub").is_err());
+        assert!(OutputTemplate::parse("../{title}.epub").is_err());
+        assert!(OutputTemplate::parse("/tmp/{title}.epub").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_long_fields() -> Result<()> {
+        let mut fields = fields();
+        fields.insert("title", "とても長いライトノベルのタイトル".repeat(10));
+        fields.insert("author", "作者".repeat(50));
+        let template = OutputTemplate::parse("{author}/{title} [{source}].epub")?;
+        let path = template.render(&fields, 60);
+        let components: Vec<_> = path.iter().map(|name| name.to_string_lossy()).collect();
+        assert_eq!(components.len(), 2);
+        assert!(components[0].len() <= 60);
+        assert!("作者".repeat(50).starts_with(components[0].as_ref()));
+        // 加上 ` (N)` 与 `.epub.tmp` 后仍不超过上限
+        let stem = components[1].strip_suffix(".epub").unwrap();
+        assert!(stem.len() + RESERVED_BYTES <= 60, "{}", stem);
+        assert!(fields["title"].starts_with(stem));
+        let path = template.render(&fields, 1);
+        assert_eq!(path.file_name().unwrap(), "book.epub");
+        Ok(())
+    }
+
+    #[test]
+    fn test_claim() -> Result<()> {
+        let dir = std::env::temp_dir().join("ranobe-output-files-test");
+        std::fs::create_dir_all(&dir)?;
+        let taken = dir.join("a.epub");
+        let owned = dir.join("b.epub");
+        std::fs::write(&taken, "")?;
+        std::fs::write(&owned, "")?;
+        let mut outputs = OutputFiles::new([owned.clone()]);
+        assert_eq!(outputs.claim(taken.clone()), dir.join("a (2).epub"));
+        assert_eq!(outputs.claim(owned.clone()), owned);
+        assert_eq!(outputs.claim(owned.clone()), dir.join("b (2).epub"));
+        assert_eq!(outputs.claim(dir.join("c.epub")), dir.join("c.epub"));
+        assert_eq!(outputs.written.len(), 4);
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
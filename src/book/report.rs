@@ -0,0 +1,71 @@
+use std::fmt;
+
+/// 下载失败的章节或插画
+#[derive(Debug, Clone)]
+pub struct Failure {
+    /// 章节标题或插画文件名
+    pub name: String,
+    pub url: String,
+    pub error: String,
+}
+
+/// 一本书下载过程中失败的章节与插画，失败的章节在 epub 中以占位页代替
+#[derive(Debug, Default, Clone)]
+pub struct FailureReport {
+    pub chapters: Vec<Failure>,
+    pub illustrations: Vec<Failure>,
+}
+
+impl FailureReport {
+    pub fn is_empty(&self) -> bool {
+        self.chapters.is_empty() && self.illustrations.is_empty()
+    }
+
+    pub fn is_failed_chapter(&self, url: &str) -> bool {
+        self.chapters.iter().any(|failure| failure.url == url)
+    }
+}
+
+impl fmt::Display for FailureReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}章下载失败，{}张插画下载失败",
+            self.chapters.len(),
+            self.illustrations.len()
+        )?;
+        for (kind, failures) in [("章节", &self.chapters), ("插画", &self.illustrations)] {
+            for failure in failures {
+                write!(
+                    f,
+                    "\n  {} {}: {} ({})",
+                    kind, failure.name, failure.error, failure.url
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut report = FailureReport::default();
+        assert!(report.is_empty());
+        report.chapters.push(Failure {
+            name: "第2章".to_string(),
+            url: "https://mock.test/2.html".to_string(),
+            error: "timeout".to_string(),
+        });
+        assert!(!report.is_empty());
+        assert!(report.is_failed_chapter("https://mock.test/2.html"));
+        assert!(!report.is_failed_chapter("https://mock.test/1.html"));
+        assert_eq!(
+            report.to_string(),
+            "1章下载失败，0张插画下载失败\n  章节 第2章: timeout (https://mock.test/2.html)"
+        );
+    }
+}
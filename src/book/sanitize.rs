@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+
+use quick_xml::escape::escape;
+use scraper::{ElementRef, Html, Node, Selector};
+
+/// 连同内容一起删除的标签
+const DROPPED_TAGS: &[&str] = &[
+    "script", "style", "iframe", "frame", "frameset", "object", "embed", "applet", "form", "input",
+    "button", "select", "textarea", "noscript", "template", "canvas", "audio", "video", "svg",
+    "math", "link", "meta", "head", "title", "base",
+];
+
+/// 保留的标签，不在列表中的标签只保留其内容
+const ALLOWED_TAGS: &[&str] = &[
+    "p",
+    "div",
+    "span",
+    "br",
+    "hr",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "em",
+    "strong",
+    "b",
+    "i",
+    "sub",
+    "sup",
+    "small",
+    "big",
+    "blockquote",
+    "pre",
+    "code",
+    "ul",
+    "ol",
+    "li",
+    "dl",
+    "dt",
+    "dd",
+    "a",
+    "img",
+    "ruby",
+    "rb",
+    "rt",
+    "rp",
+    "table",
+    "thead",
+    "tbody",
+    "tfoot",
+    "tr",
+    "th",
+    "td",
+    "caption",
+];
+
+const VOID_TAGS: &[&str] = &["br", "hr", "img"];
+
+/// 将站点抓取的 html 片段转换为格式正确的 xhtml：
+/// 删除脚本、样式与站点组件，只保留白名单中的标签与属性，
+/// 并将与 `images` 中的地址完全一致的 `img@src` 替换为对应的值，
+/// 不在 `images` 中的图片（例如下载失败的插画）直接删除，避免 epub 引用远程地址
+pub fn sanitize(fragment: &str, images: &HashMap<String, String>) -> String {
+    let html = Html::parse_fragment(fragment);
+    let mut xhtml = String::with_capacity(fragment.len());
+    write_children(html.root_element(), images, &mut xhtml);
+    xhtml
+}
+
+/// 片段中全部 `img@src` 的原始地址，按出现顺序排列
+pub fn image_sources(fragment: &str) -> Vec<String> {
+    let img_selector = Selector::parse("img[src]").expect("Failed to parse image selector");
+    Html::parse_fragment(fragment)
+        .select(&img_selector)
+        .filter_map(|img| img.value().attr("src").map(str::to_string))
+        .collect()
+}
+
+fn write_children(element: ElementRef, images: &HashMap<String, String>, xhtml: &mut String) {
+    for child in element.children() {
+        match child.value() {
+            Node::Text(text) => xhtml.push_str(&escape(strip_control_chars(text).as_str())),
+            Node::Element(_) => write_element(ElementRef::wrap(child).unwrap(), images, xhtml),
+            _ => {}
+        }
+    }
+}
+
+fn write_element(element: ElementRef, images: &HashMap<String, String>, xhtml: &mut String) {
+    let name = element.value().name();
+    if DROPPED_TAGS.contains(&name) {
+        return;
+    }
+    if !ALLOWED_TAGS.contains(&name) {
+        write_children(element, images, xhtml);
+        return;
+    }
+    if name == "img"
+        && !element
+            .value()
+            .attr("src")
+            .is_some_and(|src| images.contains_key(src))
+    {
+        return;
+    }
+    xhtml.push('<');
+    xhtml.push_str(name);
+    let mut has_alt = false;
+    for (attr, value) in element.value().attrs() {
+        if !is_allowed_attr(name, attr, value) {
+            continue;
+        }
+        has_alt |= attr == "alt";
+        let value = match (name, attr) {
+            ("img", "src") => images.get(value).map_or(value, String::as_str),
+            _ => value,
+        };
+        xhtml.push_str(&format!(
+            " {}=\"{}\"",
+            attr,
+            escape(strip_control_chars(value).as_str())
+        ));
+    }
+    // xhtml 1.1 中 img 必须带有 alt
+    if name == "img" && !has_alt {
+        xhtml.push_str(" alt=\"\"");
+    }
+    if VOID_TAGS.contains(&name) {
+        xhtml.push_str("/>");
+        return;
+    }
+    xhtml.push('>');
+    write_children(element, images, xhtml);
+    xhtml.push_str(&format!("</{}>", name));
+}
+
+fn is_allowed_attr(tag: &str, attr: &str, value: &str) -> bool {
+    match (tag, attr) {
+        (_, "class" | "title" | "dir") => true,
+        ("img", "src" | "alt") => true,
+        ("a", "href") => is_allowed_href(value),
+        ("td" | "th", "colspan" | "rowspan") => true,
+        _ => false,
+    }
+}
+
+/// 只保留绝对的 http(s) 链接与页内锚点，站内相对链接在 epub 中无法打开
+fn is_allowed_href(href: &str) -> bool {
+    let href = href.trim();
+    if href.starts_with('#') {
+        return href.len() > 1;
+    }
+    reqwest::Url::parse(href).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+/// 删除 xml 中不允许出现的控制字符
+fn strip_control_chars(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_control() || matches!(c, '\n' | '\r' | '\t'))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clean(fragment: &str) -> String {
+        sanitize(fragment, &HashMap::new())
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let html = r##"<div class="forum-content mt-3" style="color:red" onclick="x()">
+<p>第一行<br>第二行 & 第三行</p>
+<script>alert(1)</script><style>p{}</style>
+<img src="https://mock.test/a.jpg?x=1&y=2" width="100">
+<font color="red">红字</font><!-- 注释 -->
+<a href="javascript:void(0)">链接</a><a href="https://mock.test/">站点</a>
+<a href="/forum/1/2.html">站内</a><a href="../x.html">相对</a><a href="#note">脚注</a>
+<a href="mailto:a@mock.test">邮件</a><a href="//mock.test/x">协议相对</a>
+<iframe src="https://ads.test/"></iframe><u>下划线</u>
+</div>"##;
+        let xhtml = clean(html);
+        assert!(xhtml.starts_with(r#"<div class="forum-content mt-3">"#));
+        assert!(xhtml.contains("<p>第一行<br/>第二行 &amp; 第三行</p>"));
+        assert!(xhtml.contains("红字"));
+        assert!(xhtml.contains("<a>链接</a>"));
+        assert!(xhtml.contains(r#"<a href="https://mock.test/">站点</a>"#));
+        assert!(xhtml.contains(r##"<a href="#note">脚注</a>"##));
+        for dropped in ["站内", "相对", "邮件", "协议相对"] {
+            assert!(xhtml.contains(&format!("<a>{}</a>", dropped)), "{}", xhtml);
+        }
+        assert!(xhtml.contains("下划线"));
+        for removed in [
+            "script", "alert", "style", "onclick", "font", "注释", "iframe", "ads.test", "<u>",
+            "width", "<img", "a.jpg",
+        ] {
+            assert!(!xhtml.contains(removed), "{} in {}", removed, xhtml);
+        }
+        let mut reader = quick_xml::Reader::from_str(&xhtml);
+        while reader.read_event().unwrap() != quick_xml::events::Event::Eof {}
+    }
+
+    #[test]
+    fn test_rewrite_images() {
+        let html = r#"<p><img src="https://mock.test/a.png?v=2"><img src="https://mock.test/a.png"></p><img src="https://mock.test/a.png?v=2&amp;s=1"><p>https://mock.test/a.png</p>"#;
+        let images = HashMap::from([
+            (
+                "https://mock.test/a.png".to_string(),
+                "../Images/a.png".to_string(),
+            ),
+            (
+                "https://mock.test/a.png?v=2".to_string(),
+                "../Images/b.png".to_string(),
+            ),
+        ]);
+        assert_eq!(
+            sanitize(html, &images),
+            r#"<p><img src="../Images/b.png" alt=""/><img src="../Images/a.png" alt=""/></p><p>https://mock.test/a.png</p>"#
+        );
+        assert_eq!(
+            image_sources(html),
+            vec![
+                "https://mock.test/a.png?v=2",
+                "https://mock.test/a.png",
+                "https://mock.test/a.png?v=2&s=1"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sanitize_fixes_structure() {
+        assert_eq!(clean("<p>未闭合<p>段落"), "<p>未闭合</p><p>段落</p>");
+        assert_eq!(clean("a\u{0}b &nbsp;c"), "ab \u{a0}c");
+        assert_eq!(
+            clean(&clean("<p>x &amp; y<br></p>")),
+            "<p>x &amp; y<br/></p>"
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use crate::config::SelectionConfig;
+use crate::source::ChapterRef;
+use anyhow::{anyhow, Result};
+use regex::Regex;
+
+/// 以 `1-50,120-` 形式表示的编号范围，编号从 1 开始，两端均包含
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexRanges(Vec<(usize, Option<usize>)>);
+
+impl IndexRanges {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let parse_index = |value: &str| -> Result<Option<usize>> {
+            match value.trim() {
+                "" => Ok(None),
+                value => Ok(Some(
+                    value
+                        .parse::<usize>()
+                        .map_err(|_| anyhow!("无法解析的编号: {}", value))?,
+                )),
+            }
+        };
+        let ranges = spec
+            .split(',')
+            .filter(|part| !part.trim().is_empty())
+            .map(|part| match part.split_once('-') {
+                Some((start, end)) => {
+                    let (start, end) = (parse_index(start)?.unwrap_or(1), parse_index(end)?);
+                    if end.is_some_and(|end| end < start) {
+                        return Err(anyhow!("编号范围的起点大于终点: {}", part.trim()));
+                    }
+                    Ok((start, end))
+                }
+                None => {
+                    let idx = parse_index(part)?.unwrap_or(1);
+                    Ok((idx, Some(idx)))
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if ranges.is_empty() {
+            return Err(anyhow!("空的编号范围"));
+        }
+        Ok(IndexRanges(ranges))
+    }
+
+    pub fn contains(&self, idx: usize) -> bool {
+        self.0
+            .iter()
+            .any(|(start, end)| idx >= *start && end.is_none_or(|end| idx <= end))
+    }
+}
+
+/// 部分下载时的章节选择条件，多个条件同时满足的章节才会下载
+#[derive(Debug, Clone, Default)]
+pub struct ChapterSelection {
+    pub chapters: Option<IndexRanges>,
+    pub title: Option<Regex>,
+    pub volumes: Option<IndexRanges>,
+}
+
+impl ChapterSelection {
+    pub fn from_config(config: &SelectionConfig) -> Result<Self> {
+        Ok(ChapterSelection {
+            chapters: config
+                .chapters
+                .as_deref()
+                .map(IndexRanges::parse)
+                .transpose()?,
+            title: config.title.as_deref().map(Regex::new).transpose()?,
+            volumes: config
+                .volumes
+                .as_deref()
+                .map(IndexRanges::parse)
+                .transpose()?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chapters.is_none() && self.title.is_none() && self.volumes.is_none()
+    }
+
+    /// 返回选中章节在完整目录中的编号（从 1 开始）与章节，编号用作 `Episode.order`
+    pub fn select(&self, chapters: Vec<ChapterRef>) -> Vec<(u32, ChapterRef)> {
+        let mut volumes: Vec<String> = vec![];
+        chapters
+            .into_iter()
+            .enumerate()
+            .filter_map(|(idx, chapter)| {
+                let volume_idx = chapter.volume.as_ref().map(|volume| {
+                    match volumes.iter().position(|known| known == volume) {
+                        Some(pos) => pos + 1,
+                        None => {
+                            volumes.push(volume.clone());
+                            volumes.len()
+                        }
+                    }
+                });
+                let selected = self
+                    .chapters
+                    .as_ref()
+                    .is_none_or(|ranges| ranges.contains(idx + 1))
+                    && self
+                        .title
+                        .as_ref()
+                        .is_none_or(|title| title.is_match(&chapter.title))
+                    && self.volumes.as_ref().is_none_or(|ranges| {
+                        volume_idx.is_some_and(|volume_idx| ranges.contains(volume_idx))
+                    });
+                selected.then_some((idx as u32 + 1, chapter))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chapters() -> Vec<ChapterRef> {
+        (1..=8)
+            .map(|idx| ChapterRef {
+                title: match idx {
+                    1 => "简介".to_string(),
+                    idx => format!("第{}章", idx - 1),
+                },
+                url: format!("https://mock.test/{}.html", idx),
+                volume: (idx > 1).then(|| format!("第{}卷", (idx - 2) / 3 + 1)),
+            })
+            .collect()
+    }
+
+    fn orders(selection: &ChapterSelection) -> Vec<u32> {
+        selection
+            .select(chapters())
+            .into_iter()
+            .map(|(order, _)| order)
+            .collect()
+    }
+
+    #[test]
+    fn test_index_ranges() -> Result<()> {
+        let ranges = IndexRanges::parse("1-3, 5,8-")?;
+        assert!(ranges.contains(1) && ranges.contains(3));
+        assert!(!ranges.contains(4));
+        assert!(ranges.contains(5));
+        assert!(!ranges.contains(7));
+        assert!(ranges.contains(8) && ranges.contains(1000));
+        assert!(IndexRanges::parse("-2")?.contains(1));
+        assert!(IndexRanges::parse("a-b").is_err());
+        let err = IndexRanges::parse("1-2,5-3").unwrap_err();
+        assert!(err.to_string().contains("5-3"), "{}", err);
+        assert!(IndexRanges::parse("3-3")?.contains(3));
+        assert!(IndexRanges::parse("").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_select() -> Result<()> {
+        assert_eq!(
+            orders(&ChapterSelection::default()),
+            (1..=8).collect::<Vec<_>>()
+        );
+        let selection = ChapterSelection::from_config(&SelectionConfig {
+            chapters: Some("3-".to_string()),
+            ..SelectionConfig::default()
+        })?;
+        assert_eq!(orders(&selection), vec![3, 4, 5, 6, 7, 8]);
+
+        let selection = ChapterSelection::from_config(&SelectionConfig {
+            volumes: Some("2".to_string()),
+            ..SelectionConfig::default()
+        })?;
+        assert_eq!(orders(&selection), vec![5, 6, 7]);
+
+        let selection = ChapterSelection::from_config(&SelectionConfig {
+            title: Some("第[246]章".to_string()),
+            volumes: Some("1-2".to_string()),
+            ..SelectionConfig::default()
+        })?;
+        assert_eq!(orders(&selection), vec![3, 5, 7]);
+        Ok(())
+    }
+}
@@ -1,8 +1,8 @@
+use std::collections::HashSet;
 use std::vec;
 
 use anyhow::Result;
 use quick_xml::se::to_string;
-use serde;
 use serde::{Deserialize, Serialize};
 
 use crate::config::TEMPLATE;
@@ -26,14 +26,12 @@ pub struct Ncx {
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-
 pub struct Head {
     meta: Vec<Meta>,
 }
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-
 pub struct Meta {
     #[serde(rename = "@content")]
     content: String,
@@ -42,13 +40,11 @@ pub struct Meta {
 }
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-
 pub struct NavMap {
     nav_point: Vec<NavPoint>,
 }
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-
 pub struct NavPoint {
     #[serde(rename = "@id")]
     id: String,
@@ -56,6 +52,9 @@ pub struct NavPoint {
     play_order: u32,
     nav_label: Text,
     content: Content,
+    /// 分卷时卷下的章节
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    nav_point: Vec<NavPoint>,
 }
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -86,34 +85,110 @@ impl Content {
     }
 }
 
+impl NavPoint {
+    fn new(id: String, play_order: u32, label: &str, src: &str) -> Self {
+        NavPoint {
+            id,
+            play_order,
+            nav_label: Text::new(label),
+            content: Content::new(src),
+            nav_point: vec![],
+        }
+    }
+}
+
 impl Ncx {
-    pub fn new(title: &String, author: &String, episodes: &Vec<Episode>) -> Self {
-        let mut nav_points = vec![NavPoint {
-            id: "cover".to_string(),
-            play_order: 0,
-            nav_label: Text::new("封面"),
-            content: Content::new("Text/titlepage.xhtml"),
-        }];
-        for (idx, episode) in episodes.iter().enumerate() {
-            nav_points.push(NavPoint {
-                id: format!("{}{}", "ep", idx as u32 + 1),
-                play_order: idx as u32 + 1,
-                nav_label: Text::new(&episode.episode_title),
-                content: Content::new(&episode.episode_save_path),
-            });
+    /// 分卷的章节生成两级目录，卷指向其第一章并与之共用 playOrder，
+    /// 卷的 id 按其在远端目录的全部卷 `volumes` 中的位置编号，与分卷输出的卷号一致；
+    /// `about` 为 true 时在封面后加入「关于本书」
+    pub fn new(
+        identifier: &str,
+        title: &str,
+        author: &str,
+        episodes: &[Episode],
+        volumes: &[String],
+        about: bool,
+    ) -> Self {
+        let mut nav_points = vec![NavPoint::new(
+            "cover".to_string(),
+            1,
+            "封面",
+            "Text/titlepage.xhtml",
+        )];
+        if about {
+            nav_points.push(NavPoint::new(
+                "about".to_string(),
+                2,
+                "关于本书",
+                "Text/about.xhtml",
+            ));
+        }
+        let offset = nav_points.len() as u32;
+        let mut play_order = offset;
+        let mut episode_nav_point = |episode: &Episode| {
+            play_order += 1;
+            NavPoint::new(
+                format!("{}{}", "ep", play_order - offset),
+                play_order,
+                &episode.episode_title,
+                &episode.episode_save_path,
+            )
+        };
+        let mut volume_ids = HashSet::new();
+        for volume in Volume::group(episodes) {
+            let chapters = volume.episodes.iter().map(&mut episode_nav_point);
+            match volume.title {
+                Some(volume_title) => {
+                    let chapters: Vec<_> = chapters.collect();
+                    let index = volumes
+                        .iter()
+                        .position(|known| known == volume_title)
+                        .map_or(volume_ids.len(), |pos| pos)
+                        + 1;
+                    // 同一卷被未分卷的章节隔开时会出现多次，后出现的改用第一章的编号避免重复
+                    let mut id = format!("vol{}", index);
+                    if !volume_ids.insert(id.clone()) {
+                        id = format!("vol{}-{}", index, chapters[0].id);
+                    }
+                    let mut nav_point = NavPoint::new(
+                        id,
+                        chapters[0].play_order,
+                        volume_title,
+                        &chapters[0].content.src,
+                    );
+                    nav_point.nav_point = chapters;
+                    nav_points.push(nav_point);
+                }
+                None => nav_points.extend(chapters),
+            }
         }
+        // 分卷时目录为两级；没有页码列表，页数与最大页码均为 0
+        let depth = match nav_points
+            .iter()
+            .any(|nav_point| !nav_point.nav_point.is_empty())
+        {
+            true => 2,
+            false => 1,
+        };
+        let meta = [
+            (TEMPLATE.toc_meta_name.as_str(), identifier.to_string()),
+            ("dtb:depth", depth.to_string()),
+            ("dtb:totalPageCount", "0".to_string()),
+            ("dtb:maxPageNumber", "0".to_string()),
+        ]
+        .into_iter()
+        .map(|(name, content)| Meta {
+            content,
+            name: name.to_string(),
+        })
+        .collect();
         Ncx {
             prefix: TEMPLATE.toc_prefix.clone(),
             version: TEMPLATE.toc_verison.clone(),
             xmlns: TEMPLATE.toc_xmlns.clone(),
-            head: Head {
-                meta: vec![Meta {
-                    content: TEMPLATE.toc_meta_content.clone(),
-                    name: TEMPLATE.toc_meta_name.clone(),
-                }],
-            },
-            doc_title: Text::new(&title),
-            doc_author: Text::new(&author),
+            head: Head { meta },
+            doc_title: Text::new(title),
+            doc_author: Text::new(author),
             nav_map: NavMap {
                 nav_point: nav_points,
             },
@@ -134,25 +209,96 @@ mod tests {
 
     use super::*;
 
+    fn episode(order: u32, title: &str, volume: Option<&str>) -> Episode {
+        Episode {
+            episode_title: title.to_string(),
+            content: "cnm".to_string(),
+            episode_save_path: format!("Text/{}.xhtml", order),
+            order,
+            url: format!("https://www.esjzone.me/forum/1/{}.html", order),
+            volume: volume.map(str::to_string),
+        }
+    }
+
     #[test]
     fn test_struct() -> Result<()> {
-        let episode = Episode {
-            episode_title: "设定总和".to_string(),
-            content: "cnm".to_string(),
-            episode_save_path: "Text/1.xhmtl".to_string(),
-            order: 1,
-        };
-        let episode2 = Episode {
-            episode_title: "第一章".to_string(),
-            content: "nmsl".to_string(),
-            episode_save_path: "Text/2.xhmtl".to_string(),
-            order: 2,
-        };
-        let episodes = vec![episode, episode2];
-        let ncx = Ncx::new(&"haha".to_string(), &"fufu".to_string(), &episodes);
-        let res = ncx.content().unwrap();
-        println!("{:?}", res);
-        std::fs::write("./toc.ncx", res)?;
+        let episodes = vec![episode(1, "设定总和", None), episode(2, "第一章", None)];
+        let ncx = Ncx::new("urn:uuid:0", "haha", "fufu", &episodes, &[], false);
+        let res = ncx.content()?;
+        assert!(res.starts_with(&TEMPLATE.toc_prefix));
+        assert!(
+            res.contains(r#"<ncx version="2005-1" xmlns="http://www.daisy.org/z3986/2005/ncx/">"#)
+        );
+        assert!(res.contains(r#"<head><meta content="urn:uuid:0" name="dtb:uid"/><meta content="1" name="dtb:depth"/><meta content="0" name="dtb:totalPageCount"/><meta content="0" name="dtb:maxPageNumber"/></head>"#));
+        assert!(res.contains(
+            "<docTitle><text>haha</text></docTitle><docAuthor><text>fufu</text></docAuthor>"
+        ));
+        assert!(res.contains(r#"<navPoint id="cover" playOrder="1"><navLabel><text>封面</text></navLabel><content src="Text/titlepage.xhtml"/></navPoint>"#));
+        assert!(res.contains(r#"<navPoint id="ep1" playOrder="2"><navLabel><text>设定总和</text></navLabel><content src="Text/1.xhtml"/></navPoint>"#));
+        assert!(res.contains(r#"<navPoint id="ep2" playOrder="3">"#));
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_volumes() -> Result<()> {
+        let episodes = vec![
+            episode(1, "简介", None),
+            episode(2, "第一章", Some("第一卷")),
+            episode(3, "第二章", Some("第一卷")),
+            episode(5, "第三章", Some("第二卷")),
+        ];
+        let volumes = ["第一卷".to_string(), "第二卷".to_string()];
+        let ncx = Ncx::new("urn:uuid:0", "haha", "fufu", &episodes, &volumes, false);
+        let nav_points = &ncx.nav_map.nav_point;
+        assert_eq!(nav_points.len(), 4);
+        assert_eq!(nav_points[1].nav_label.text, "简介");
+        let volume = &nav_points[2];
+        assert_eq!(volume.nav_label.text, "第一卷");
+        assert_eq!(volume.content.src, "Text/2.xhtml");
+        assert_eq!(volume.play_order, 3);
+        let orders: Vec<_> = volume.nav_point.iter().map(|ep| ep.play_order).collect();
+        assert_eq!(orders, vec![3, 4]);
+        assert_eq!(nav_points[3].nav_point[0].play_order, 5);
+        assert_eq!(nav_points[3].nav_point[0].content.src, "Text/5.xhtml");
+        // 卷的 id 按卷号编号，不计入未分卷的章节
+        assert_eq!(volume.id, "vol1");
+        assert_eq!(nav_points[3].id, "vol2");
+        let res = ncx.content()?;
+        assert!(res.contains(r#"<meta content="2" name="dtb:depth"/>"#));
+        assert!(res.contains(
+            "<navLabel><text>第一卷</text></navLabel><content src=\"Text/2.xhtml\"/><navPoint id=\"ep2\""
+        ));
+
+        let ncx = Ncx::new(
+            "urn:uuid:0",
+            "haha",
+            "fufu",
+            &episodes[3..],
+            &volumes,
+            false,
+        );
+        assert_eq!(ncx.nav_map.nav_point[1].id, "vol2");
+        // 同一卷被未分卷的章节隔开时 id 不重复
+        let interleaved = [
+            episodes[1].clone(),
+            episodes[0].clone(),
+            episodes[2].clone(),
+        ];
+        let ncx = Ncx::new("urn:uuid:0", "haha", "fufu", &interleaved, &volumes, false);
+        let ids: Vec<_> = ncx
+            .nav_map
+            .nav_point
+            .iter()
+            .map(|p| p.id.as_str())
+            .collect();
+        assert_eq!(ids, ["cover", "vol1", "ep2", "vol1-ep3"]);
+
+        let ncx = Ncx::new("urn:uuid:0", "haha", "fufu", &episodes, &[], true);
+        let nav_points = &ncx.nav_map.nav_point;
+        assert_eq!(nav_points[1].content.src, "Text/about.xhtml");
+        assert_eq!(nav_points[1].play_order, 2);
+        assert_eq!(nav_points[2].id, "ep1");
+        assert_eq!(nav_points[2].play_order, 3);
         Ok(())
     }
 }
@@ -0,0 +1,56 @@
+use super::Episode;
+
+/// 目录中连续属于同一卷的章节，未分卷的章节单独成组且 `title` 为空
+pub struct Volume<'a> {
+    pub title: Option<&'a str>,
+    pub episodes: &'a [Episode],
+}
+
+impl<'a> Volume<'a> {
+    /// 按卷名将相邻章节分组，保持章节原有顺序
+    pub fn group(episodes: &'a [Episode]) -> Vec<Volume<'a>> {
+        episodes
+            .chunk_by(|a, b| a.volume == b.volume)
+            .map(|episodes| Volume {
+                title: episodes[0].volume.as_deref(),
+                episodes,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(order: u32, volume: Option<&str>) -> Episode {
+        Episode {
+            episode_title: format!("第{}章", order),
+            content: String::new(),
+            episode_save_path: format!("Text/{}.xhtml", order),
+            order,
+            url: format!("https://mock.test/{}.html", order),
+            volume: volume.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_group() {
+        let episodes = vec![
+            episode(1, None),
+            episode(2, Some("第一卷")),
+            episode(3, Some("第一卷")),
+            episode(4, Some("第二卷")),
+        ];
+        let volumes = Volume::group(&episodes);
+        let groups: Vec<_> = volumes
+            .iter()
+            .map(|volume| (volume.title, volume.episodes.len()))
+            .collect();
+        assert_eq!(
+            groups,
+            vec![(None, 1), (Some("第一卷"), 2), (Some("第二卷"), 1)]
+        );
+        assert!(Volume::group(&[]).is_empty());
+    }
+}
@@ -0,0 +1,563 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+use tracing::{error, info, warn};
+
+use crate::book::{Book, Cache, ChapterSelection, DownloadOptions, FailureReport};
+use crate::config::{Config, NovelUrl, OutputFormat, SelectionConfig, Template};
+use crate::library::Library;
+use crate::source;
+use crate::{validator, Downloader, CONFIG};
+
+/// 轻小说爬取下载，生成epub文件
+#[derive(Parser, Debug)]
+#[command(name = "ranobe-downloader", version)]
+pub struct Cli {
+    /// 配置文件路径
+    #[arg(short, long, global = true, default_value = Config::DEFAULT_PATH)]
+    pub config: PathBuf,
+
+    /// 覆盖 esj_output_path
+    #[arg(short, long, global = true)]
+    pub output: Option<String>,
+
+    /// 覆盖 download_config.concurrency
+    #[arg(short = 'j', long, global = true)]
+    pub concurrency: Option<usize>,
+
+    /// 从单独的 yaml 文件读取 ews_key 与 ews_token
+    #[arg(long, global = true)]
+    pub credentials: Option<PathBuf>,
+
+    /// 覆盖 epub_config.format
+    #[arg(short, long, global = true, value_enum)]
+    pub format: Option<OutputFormat>,
+
+    /// 每卷单独生成一个 epub
+    #[arg(long, global = true, overrides_with = "no_split_volumes")]
+    pub split_volumes: bool,
+
+    /// 不按卷拆分，覆盖配置中的 `split_volumes: true`
+    #[arg(long, global = true, overrides_with = "split_volumes")]
+    pub no_split_volumes: bool,
+
+    /// 另外写出解包后的 epub 内容，用于调试
+    #[arg(long, global = true, overrides_with = "no_staging")]
+    pub staging: bool,
+
+    /// 不写出解包后的 epub 内容
+    #[arg(long, global = true, overrides_with = "staging")]
+    pub no_staging: bool,
+
+    /// 将 WebP、AVIF、BMP 插画转换为 JPEG 或 PNG
+    #[arg(long, global = true, overrides_with = "no_convert_images")]
+    pub convert_images: bool,
+
+    /// 不转换插画格式
+    #[arg(long, global = true, overrides_with = "convert_images")]
+    pub no_convert_images: bool,
+
+    /// 覆盖 epub_config.device_profile
+    #[arg(long, global = true)]
+    pub device_profile: Option<String>,
+
+    /// 覆盖 epub_config.output_template，如 `{author}/{title}.epub`
+    #[arg(long, global = true)]
+    pub output_template: Option<String>,
+
+    /// 只使用小说目录中的下载缓存，不访问网络
+    #[arg(long, global = true, overrides_with = "no_offline")]
+    pub offline: bool,
+
+    /// 访问网络下载，覆盖配置中的 `offline: true`
+    #[arg(long, global = true, overrides_with = "offline")]
+    pub no_offline: bool,
+
+    /// 未指定子命令时等同于 `batch`
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// 下载指定的小说
+    Download {
+        #[arg(required = true)]
+        urls: Vec<String>,
+        #[command(flatten)]
+        selection: SelectionConfig,
+    },
+    /// 增量更新，只下载新增或变化的章节，未指定 url 时更新书库中的全部小说
+    Update {
+        urls: Vec<String>,
+        #[command(flatten)]
+        selection: SelectionConfig,
+    },
+    /// 下载配置文件中 esj_novel_urls 的全部小说
+    Batch {
+        /// 以增量更新模式运行
+        #[arg(long)]
+        update: bool,
+    },
+    /// 列出书库中的小说
+    List,
+    /// 查看书库中小说的详情
+    Info { url: String },
+    /// 从书库中移除小说记录
+    Remove { url: String },
+    /// 不访问网络，使用本地缓存重新生成 epub，未指定 url 时导出书库中的全部小说
+    Export { urls: Vec<String> },
+    /// 检查 epub 文件的结构
+    Validate {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// 配置相关命令
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// 缓存相关命令
+    #[command(subcommand)]
+    Cache(CacheCommand),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// 检查配置文件
+    Check,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheCommand {
+    /// 删除 HTTP 缓存以及各小说目录下的下载缓存
+    Clear,
+}
+
+/// `--xxx` 与 `--no-xxx` 成对的开关，都未指定时沿用配置文件，后出现的优先
+fn switch(on: bool, off: bool) -> Option<bool> {
+    match (on, off) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+impl Cli {
+    /// 读取配置文件并应用命令行参数的覆盖
+    pub fn load_config(&self) -> Result<Config> {
+        let mut config = Config::load_from(&self.config)?;
+        if let Some(output) = &self.output {
+            config.esj_zone_config.esj_output_path = output.clone();
+        }
+        if let Some(concurrency) = self.concurrency {
+            config.download_config.concurrency = concurrency;
+        }
+        if let Some(credentials) = &self.credentials {
+            config.load_credentials(credentials)?;
+        }
+        if let Some(format) = self.format {
+            config.epub_config.format = format;
+        }
+        if let Some(split_volumes) = switch(self.split_volumes, self.no_split_volumes) {
+            config.epub_config.split_volumes = split_volumes;
+        }
+        if let Some(staging) = switch(self.staging, self.no_staging) {
+            config.epub_config.staging = staging;
+        }
+        if let Some(convert_images) = switch(self.convert_images, self.no_convert_images) {
+            config.epub_config.convert_images = convert_images;
+        }
+        if let Some(device_profile) = &self.device_profile {
+            config.epub_config.device_profile = Some(device_profile.clone());
+        }
+        if let Some(output_template) = &self.output_template {
+            config.epub_config.output_template = output_template.clone();
+        }
+        if let Some(offline) = switch(self.offline, self.no_offline) {
+            config.cache_config.offline = offline;
+        }
+        Ok(config)
+    }
+
+    pub async fn run(self) -> Result<()> {
+        match self.command.unwrap_or(Command::Batch { update: false }) {
+            Command::Download { urls, selection } => {
+                download(&with_selection(urls, &selection), false).await
+            }
+            Command::Update { urls, selection } => {
+                let urls = match urls.is_empty() {
+                    true => library_urls().await?,
+                    false => urls,
+                };
+                download(&with_selection(urls, &selection), true).await
+            }
+            Command::Batch { update } => {
+                download(&CONFIG.esj_zone_config.esj_novel_urls, update).await
+            }
+            Command::List => {
+                let library = Library::load(&Library::default_path()).await?;
+                for book in &library.books {
+                    book.print_summary();
+                }
+                println!("共{}本", library.books.len());
+                Ok(())
+            }
+            Command::Info { url } => {
+                let library = Library::load(&Library::default_path()).await?;
+                library
+                    .get(&url)
+                    .ok_or_else(|| anyhow!("书库中没有该小说: {}", url))?
+                    .print();
+                Ok(())
+            }
+            Command::Remove { url } => {
+                let mut library = Library::load(&Library::default_path()).await?;
+                let book = library
+                    .remove(&url)
+                    .ok_or_else(|| anyhow!("书库中没有该小说: {}", url))?;
+                library.save().await?;
+                println!("已从书库移除《{}》", book.title);
+                Ok(())
+            }
+            Command::Export { urls } => export(&urls).await,
+            Command::Validate { files } => validate(&files),
+            Command::Config(ConfigCommand::Check) => check_config(),
+            Command::Cache(CacheCommand::Clear) => clear_cache().await,
+        }
+    }
+}
+
+async fn library_urls() -> Result<Vec<String>> {
+    let library = Library::load(&Library::default_path()).await?;
+    Ok(library.books.into_iter().map(|book| book.url).collect())
+}
+
+/// 命令行中的章节选择条件对每个 url 都生效
+fn with_selection(urls: Vec<String>, selection: &SelectionConfig) -> Vec<NovelUrl> {
+    urls.into_iter()
+        .map(|url| NovelUrl::WithSelection {
+            url,
+            selection: selection.clone(),
+        })
+        .collect()
+}
+
+/// 依次下载每本小说，单本失败时记录原因并继续下一本，全部完成后输出汇总
+async fn download(urls: &[NovelUrl], update: bool) -> Result<()> {
+    let downloader = Downloader::new(&CONFIG.download_config)?;
+    let mut summary = BatchSummary::default();
+    for novel_url in urls {
+        let url = novel_url.url();
+        let res = async {
+            let options = DownloadOptions {
+                update,
+                selection: ChapterSelection::from_config(&novel_url.selection())?,
+            };
+            let source = source::from_url(url, &downloader)
+                .ok_or_else(|| anyhow!("不支持的小说地址: {}", url))?;
+            Book::gen_epub(url, source, &options).await
+        }
+        .await;
+        if let Err(err) = &res {
+            error!("{} 生成失败: {:#}", url, err);
+        }
+        summary.add(url, res);
+    }
+    if let Some(hits) = downloader.http_cache_hits() {
+        info!("HTTP缓存命中{}次", hits);
+    }
+    summary.finish()
+}
+
+/// 批量下载的结果汇总
+#[derive(Default)]
+struct BatchSummary {
+    succeeded: usize,
+    /// 生成了不完整的 epub 的小说及其失败的章节与插画
+    partial: Vec<(String, FailureReport)>,
+    failed: Vec<(String, String)>,
+}
+
+impl BatchSummary {
+    fn add(&mut self, url: &str, res: Result<FailureReport>) {
+        match res {
+            Ok(report) if report.is_empty() => self.succeeded += 1,
+            Ok(report) => self.partial.push((url.to_string(), report)),
+            Err(err) => self.failed.push((url.to_string(), format!("{:#}", err))),
+        }
+    }
+
+    /// 输出汇总，有未完成的小说时返回错误，使进程以非零状态退出
+    fn finish(self) -> Result<()> {
+        println!(
+            "共{}本：成功{}本，部分完成{}本，失败{}本",
+            self.succeeded + self.partial.len() + self.failed.len(),
+            self.succeeded,
+            self.partial.len(),
+            self.failed.len()
+        );
+        for (url, report) in &self.partial {
+            println!("部分完成 {}: {}", url, report);
+        }
+        for (url, err) in &self.failed {
+            println!("失败 {}: {}", url, err);
+        }
+        match self.partial.len() + self.failed.len() {
+            0 => Ok(()),
+            count => Err(anyhow!("{}本小说未能完整下载", count)),
+        }
+    }
+}
+
+async fn export(urls: &[String]) -> Result<()> {
+    let library = Library::load(&Library::default_path()).await?;
+    let books: Vec<_> = match urls.is_empty() {
+        true => library.books.iter().collect(),
+        false => urls
+            .iter()
+            .map(|url| {
+                library
+                    .get(url)
+                    .ok_or_else(|| anyhow!("书库中没有该小说: {}", url))
+            })
+            .collect::<Result<_>>()?,
+    };
+    for book in books {
+        Book::export(&book.save_path).await?;
+    }
+    Ok(())
+}
+
+fn validate(files: &[PathBuf]) -> Result<()> {
+    let mut invalid = 0;
+    for file in files {
+        let report = validator::validate(file)?;
+        for warning in &report.warnings {
+            warn!("{}: 警告: {}", file.display(), warning);
+        }
+        for err in &report.errors {
+            error!("{}: 错误: {}", file.display(), err);
+        }
+        println!(
+            "{}: {}个错误，{}个警告",
+            file.display(),
+            report.errors.len(),
+            report.warnings.len()
+        );
+        if !report.is_valid() {
+            invalid += 1;
+        }
+    }
+    match invalid {
+        0 => Ok(()),
+        invalid => Err(anyhow!("{}个文件未通过检查", invalid)),
+    }
+}
+
+async fn clear_cache() -> Result<()> {
+    let mut dirs = vec![PathBuf::from(&CONFIG.download_config.http_cache.path)];
+    if let Ok(mut novels) = tokio::fs::read_dir(&CONFIG.esj_zone_config.esj_root_path).await {
+        while let Some(novel) = novels.next_entry().await? {
+            dirs.push(novel.path().join(Cache::DIR_NAME));
+        }
+    }
+    let mut freed = 0;
+    for dir in dirs.into_iter().filter(|dir| dir.is_dir()) {
+        freed += dir_size(&dir)?;
+        tokio::fs::remove_dir_all(&dir).await?;
+    }
+    println!("已清除缓存，释放{:.2}MB", freed as f64 / 1024.0 / 1024.0);
+    Ok(())
+}
+
+fn dir_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        size += match entry.file_type()?.is_dir() {
+            true => dir_size(&entry.path())?,
+            false => entry.metadata()?.len(),
+        };
+    }
+    Ok(size)
+}
+
+fn check_config() -> Result<()> {
+    let (mut errors, warnings) = CONFIG.check();
+    if let Err(err) = Template::default().load() {
+        errors.push(format!("config/template.yaml 读取失败: {}", err));
+    }
+    match Downloader::new(&CONFIG.download_config) {
+        Ok(downloader) => {
+            for novel_url in &CONFIG.esj_zone_config.esj_novel_urls {
+                if source::from_url(novel_url.url(), &downloader).is_none() {
+                    errors.push(format!("不支持的小说地址: {}", novel_url.url()));
+                }
+            }
+        }
+        Err(err) => errors.push(format!("下载器初始化失败: {}", err)),
+    }
+    for warning in &warnings {
+        warn!("警告: {}", warning);
+    }
+    for err in &errors {
+        error!("错误: {}", err);
+    }
+    match errors.is_empty() {
+        true => {
+            println!("配置检查通过，{}个警告", warnings.len());
+            Ok(())
+        }
+        false => Err(anyhow!("配置检查失败，{}个错误", errors.len())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_cache_clear() -> Result<()> {
+        let cli = Cli::try_parse_from(["ranobe-downloader", "cache", "clear"])?;
+        assert!(matches!(
+            cli.command,
+            Some(Command::Cache(CacheCommand::Clear))
+        ));
+        let dir = std::env::temp_dir().join("ranobe-dir-size-test");
+        std::fs::create_dir_all(dir.join("pages"))?;
+        std::fs::write(dir.join("a.body"), [0u8; 10])?;
+        std::fs::write(dir.join("pages").join("b.html"), [0u8; 5])?;
+        assert_eq!(dir_size(&dir)?, 15);
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_overrides() -> Result<()> {
+        let cli = Cli::try_parse_from([
+            "ranobe-downloader",
+            "download",
+            "https://www.esjzone.me/detail/1719148048.html",
+            "--output",
+            "./out",
+            "-j",
+            "2",
+            "--format",
+            "epub2",
+            "--split-volumes",
+            "--convert-images",
+            "--device-profile",
+            "kobo",
+            "--offline",
+            "--output-template",
+            "{author}/{title}.epub",
+        ])?;
+        assert!(matches!(&cli.command, Some(Command::Download { urls, .. }) if urls.len() == 1));
+        let config = cli.load_config()?;
+        assert_eq!(config.esj_zone_config.esj_output_path, "./out");
+        assert_eq!(config.download_config.concurrency, 2);
+        assert_eq!(config.epub_config.format, OutputFormat::Epub2);
+        assert!(config.epub_config.split_volumes);
+        assert!(config.epub_config.convert_images);
+        assert_eq!(config.epub_config.device_profile().unwrap().max_width, 1264);
+        assert!(config.cache_config.offline);
+        assert_eq!(config.epub_config.output_template, "{author}/{title}.epub");
+
+        // 配置文件中开启的选项可以在命令行关闭
+        let config_path = std::env::temp_dir().join("ranobe-switch-test.yaml");
+        let content = ["split_volumes", "staging", "convert_images", "offline"]
+            .iter()
+            .fold(
+                std::fs::read_to_string(Config::DEFAULT_PATH)?,
+                |content, name| {
+                    content.replace(&format!("{}: false", name), &format!("{}: true", name))
+                },
+            );
+        std::fs::write(&config_path, content)?;
+        let cli = Cli::try_parse_from([
+            "ranobe-downloader",
+            "--config",
+            config_path.to_str().unwrap(),
+            "--split-volumes",
+            "--no-split-volumes",
+            "--no-staging",
+            "--no-convert-images",
+            "--offline",
+            "--no-offline",
+        ])?;
+        assert_eq!(switch(cli.split_volumes, cli.no_split_volumes), Some(false));
+        assert_eq!(switch(cli.staging, cli.no_staging), Some(false));
+        assert_eq!(switch(cli.offline, cli.no_offline), Some(false));
+        let config = cli.load_config()?;
+        assert!(!config.epub_config.split_volumes);
+        assert!(!config.epub_config.staging);
+        assert!(!config.epub_config.convert_images);
+        assert!(!config.cache_config.offline);
+        let cli = Cli::try_parse_from([
+            "ranobe-downloader",
+            "--config",
+            config_path.to_str().unwrap(),
+        ])?;
+        let config = cli.load_config()?;
+        assert!(config.epub_config.split_volumes && config.epub_config.convert_images);
+        std::fs::remove_file(&config_path)?;
+        let cli = Cli::try_parse_from(["ranobe-downloader", "--no-staging", "--staging"])?;
+        assert_eq!(switch(cli.staging, cli.no_staging), Some(true));
+        let cli = Cli::try_parse_from(["ranobe-downloader"])?;
+        assert_eq!(switch(cli.staging, cli.no_staging), None);
+
+        let cli = Cli::try_parse_from([
+            "ranobe-downloader",
+            "update",
+            "https://www.esjzone.me/detail/1719148048.html",
+            "--chapters",
+            "120-",
+            "--volumes",
+            "3-5",
+        ])?;
+        let Some(Command::Update { selection, .. }) = &cli.command else {
+            panic!("expected update command");
+        };
+        assert_eq!(selection.chapters.as_deref(), Some("120-"));
+        assert_eq!(selection.volumes.as_deref(), Some("3-5"));
+        assert!(selection.title.is_none());
+
+        let cli = Cli::try_parse_from(["ranobe-downloader"])?;
+        assert!(cli.command.is_none());
+        assert!(Cli::try_parse_from(["ranobe-downloader", "download"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_summary() {
+        let mut summary = BatchSummary::default();
+        summary.add("https://mock.test/1.html", Ok(FailureReport::default()));
+        assert!(BatchSummary {
+            succeeded: 1,
+            ..BatchSummary::default()
+        }
+        .finish()
+        .is_ok());
+
+        let mut partial = FailureReport::default();
+        partial.chapters.push(crate::book::Failure {
+            name: "第2章".to_string(),
+            url: "https://mock.test/2/2.html".to_string(),
+            error: "timeout".to_string(),
+        });
+        summary.add("https://mock.test/2.html", Ok(partial));
+        summary.add("https://mock.test/3.html", Err(anyhow!("不支持的小说地址")));
+        assert_eq!(summary.succeeded, 1);
+        assert_eq!(summary.partial.len(), 1);
+        assert_eq!(summary.failed[0].1, "不支持的小说地址");
+        let err = summary.finish().unwrap_err();
+        assert_eq!(err.to_string(), "2本小说未能完整下载");
+    }
+}
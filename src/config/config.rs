@@ -1,20 +1,315 @@
+use std::collections::BTreeMap;
 use std::fs;
+use std::path::Path;
 
 use anyhow::Result;
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
-use serde_yaml;
+
+use crate::book::{ChapterSelection, OutputTemplate};
+
+/// 章节选择条件，未填写的条件不做限制
+#[derive(Serialize, Deserialize, Debug, Clone, Default, clap::Args)]
+pub struct SelectionConfig {
+    /// 章节编号范围，如 `120-`、`1-10,20-30`
+    #[arg(long)]
+    pub chapters: Option<String>,
+    /// 章节标题需要匹配的正则表达式
+    #[arg(long)]
+    pub title: Option<String>,
+    /// 卷编号范围，按目录中卷出现的顺序从 1 开始编号，如 `3-5`
+    #[arg(long)]
+    pub volumes: Option<String>,
+}
+
+/// `esj_novel_urls` 中的一项，可以只写地址，也可以附带章节选择条件
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum NovelUrl {
+    Url(String),
+    WithSelection {
+        url: String,
+        #[serde(flatten)]
+        selection: SelectionConfig,
+    },
+}
+
+impl NovelUrl {
+    pub fn url(&self) -> &str {
+        match self {
+            NovelUrl::Url(url) | NovelUrl::WithSelection { url, .. } => url,
+        }
+    }
+
+    pub fn selection(&self) -> SelectionConfig {
+        match self {
+            NovelUrl::Url(_) => SelectionConfig::default(),
+            NovelUrl::WithSelection { selection, .. } => selection.clone(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct EsjZoneConfig {
     pub ews_key: String,
     pub ews_token: String,
     pub esj_root_path: String,
     pub esj_output_path: String,
-    pub esj_novel_urls: Vec<String>,
+    pub esj_novel_urls: Vec<NovelUrl>,
+    /// esjzone 专用的限速配置，未设置时使用全局配置
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// 首次请求失败后的最大重试次数
+    pub max_retries: u32,
+    /// 指数退避的初始等待时间
+    pub base_delay_ms: u64,
+    /// 单次等待时间上限
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_retries: 3,
+            base_delay_ms: 500,
+            max_delay_ms: 30_000,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// 每秒补充的令牌数，0 表示不限速
+    pub requests_per_second: f64,
+    /// 令牌桶容量，即允许的突发请求数
+    pub burst: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            requests_per_second: 4.0,
+            burst: 8,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HttpCacheConfig {
+    /// 缓存带有 ETag 或 Last-Modified 的响应，之后发送条件请求，304 时使用缓存的内容
+    pub enabled: bool,
+    pub path: String,
+    /// 缓存目录的大小上限，超出时删除最早写入的条目，0 表示不限制
+    pub max_size_mb: u64,
+}
+
+impl Default for HttpCacheConfig {
+    fn default() -> Self {
+        HttpCacheConfig {
+            enabled: true,
+            path: "./.http_cache".to_string(),
+            max_size_mb: 512,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct DownloadConfig {
+    /// 同一站点同时进行的最大请求数，章节与插画共用
+    pub concurrency: usize,
+    pub user_agent: String,
+    /// 单个请求的总超时时间
+    pub timeout_secs: u64,
+    pub connect_timeout_secs: u64,
+    /// 代理地址，支持 http/https/socks5
+    pub proxy: Option<String>,
+    pub retry: RetryConfig,
+    pub rate_limit: RateLimitConfig,
+    pub http_cache: HttpCacheConfig,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        DownloadConfig {
+            concurrency: 8,
+            user_agent: "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/123.0.0.0 Safari/537.36".to_string(),
+            timeout_secs: 60,
+            connect_timeout_secs: 10,
+            proxy: None,
+            retry: RetryConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            http_cache: HttpCacheConfig::default(),
+        }
+    }
+}
+
+/// 输出格式
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Epub2,
+    Epub3,
+}
+
+/// 阅读设备的图片配置，打包前按此缩放图片并重新压缩
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct DeviceProfile {
+    /// 最大宽度，超出时按比例缩小
+    pub max_width: u32,
+    /// 最大高度，超出时按比例缩小
+    pub max_height: u32,
+    /// 重新压缩 JPEG 时使用的质量，1-100
+    pub jpeg_quality: u8,
+    /// 转换为灰度图，适用于墨水屏
+    pub grayscale: bool,
+}
+
+impl Default for DeviceProfile {
+    fn default() -> Self {
+        DeviceProfile {
+            max_width: 1600,
+            max_height: 2560,
+            jpeg_quality: 85,
+            grayscale: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct EpubConfig {
+    pub format: OutputFormat,
+    /// 每卷单独生成一个 epub
+    pub split_volumes: bool,
+    /// 另外在小说目录的 `staging` 下写出解包后的 epub 内容，用于调试
+    pub staging: bool,
+    /// 将 WebP、AVIF、BMP 插画转换为 JPEG 或 PNG
+    pub convert_images: bool,
+    /// 使用的设备配置名称，为空时保留原图
+    pub device_profile: Option<String>,
+    /// 可选的设备配置，默认包含 kindle、kobo 与 tablet
+    pub device_profiles: BTreeMap<String, DeviceProfile>,
+    /// 输出文件名模板，相对于 `esj_output_path`
+    pub output_template: String,
+    /// 在封面后加入包含简介、标签等信息的「关于本书」页面
+    pub about_page: bool,
+}
+
+impl Default for EpubConfig {
+    fn default() -> Self {
+        let profile = |max_width, max_height, jpeg_quality, grayscale| DeviceProfile {
+            max_width,
+            max_height,
+            jpeg_quality,
+            grayscale,
+        };
+        EpubConfig {
+            format: OutputFormat::default(),
+            split_volumes: false,
+            staging: false,
+            convert_images: false,
+            device_profile: None,
+            device_profiles: BTreeMap::from([
+                ("kindle".to_string(), profile(1072, 1448, 75, true)),
+                ("kobo".to_string(), profile(1264, 1680, 80, true)),
+                ("tablet".to_string(), profile(1600, 2560, 85, false)),
+            ]),
+            output_template: "{title}.epub".to_string(),
+            about_page: false,
+        }
+    }
+}
+
+impl EpubConfig {
+    /// 当前使用的设备配置
+    pub fn device_profile(&self) -> Option<&DeviceProfile> {
+        self.device_profile
+            .as_ref()
+            .and_then(|name| self.device_profiles.get(name))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// 下载时立即写入章节页面、解析结果与插画，中断后重新运行时沿用中断前已获取的章节
+    pub enabled: bool,
+    /// 只使用缓存，不访问网络
+    pub offline: bool,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            enabled: true,
+            offline: false,
+        }
+    }
+}
+
+/// 由书名等生成文件名与目录名时的规则
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct FilenameConfig {
+    /// 替换的字符串及替换后的内容，默认将文件名中不允许的字符替换为对应的全角字符
+    pub replacements: BTreeMap<String, String>,
+    /// 替换后仍不允许出现的字符（如控制字符）统一替换为该字符串
+    pub replacement: String,
+    /// 文件名（不含扩展名）的最大字节数，超出时按字符截断；
+    /// 输出的 epub 文件名连同重名序号与临时文件的扩展名也不超过该值
+    pub max_bytes: usize,
+}
+
+impl Default for FilenameConfig {
+    fn default() -> Self {
+        FilenameConfig {
+            replacements: [
+                ("/", "／"),
+                ("\\", "＼"),
+                (":", "："),
+                ("*", "＊"),
+                ("?", "？"),
+                ("\"", "＂"),
+                ("<", "＜"),
+                (">", "＞"),
+                ("|", "｜"),
+            ]
+            .into_iter()
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect(),
+            replacement: "_".to_string(),
+            max_bytes: 200,
+        }
+    }
+}
+
+/// 单独存放的登录凭据文件，格式与 `esj_zone_config` 中的同名字段一致
+#[derive(Deserialize, Debug)]
+pub struct CredentialFile {
+    pub ews_key: String,
+    pub ews_token: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
     pub esj_zone_config: EsjZoneConfig,
+    #[serde(default)]
+    pub download_config: DownloadConfig,
+    #[serde(default)]
+    pub epub_config: EpubConfig,
+    #[serde(default)]
+    pub cache_config: CacheConfig,
+    #[serde(default)]
+    pub filename_config: FilenameConfig,
 }
 
 impl Default for Config {
@@ -26,14 +321,204 @@ impl Default for Config {
                 esj_root_path: String::new(),
                 esj_output_path: String::new(),
                 esj_novel_urls: vec![],
+                rate_limit: None,
             },
+            download_config: DownloadConfig::default(),
+            epub_config: EpubConfig::default(),
+            cache_config: CacheConfig::default(),
+            filename_config: FilenameConfig::default(),
         }
     }
 }
 
 impl Config {
+    pub const DEFAULT_PATH: &'static str = "config/config.yaml";
+
     pub fn load(&self) -> Result<Self> {
-        let content = fs::read_to_string("config/config.yaml")?;
+        Config::load_from(Path::new(Config::DEFAULT_PATH))
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
         Ok(serde_yaml::from_str(&content)?)
     }
+
+    /// 用凭据文件覆盖配置中的 `ews_key` 与 `ews_token`
+    pub fn load_credentials(&mut self, path: &Path) -> Result<()> {
+        let content = fs::read_to_string(path)?;
+        let credential: CredentialFile = serde_yaml::from_str(&content)?;
+        self.esj_zone_config.ews_key = credential.ews_key;
+        self.esj_zone_config.ews_token = credential.ews_token;
+        Ok(())
+    }
+
+    /// 检查配置，返回 (错误, 警告)
+    pub fn check(&self) -> (Vec<String>, Vec<String>) {
+        let mut errors = vec![];
+        let mut warnings = vec![];
+        let esj = &self.esj_zone_config;
+        if esj.esj_root_path.is_empty() {
+            errors.push("esj_root_path 不能为空".to_string());
+        }
+        if esj.esj_output_path.is_empty() {
+            errors.push("esj_output_path 不能为空".to_string());
+        }
+        if esj.ews_key.is_empty() || esj.ews_token.is_empty() {
+            warnings.push("未设置 ews_key/ews_token，需要登录才能查看的章节将无法下载".to_string());
+        }
+        for novel_url in &esj.esj_novel_urls {
+            if Url::parse(novel_url.url()).is_err() {
+                errors.push(format!(
+                    "esj_novel_urls 中的地址无法解析: {}",
+                    novel_url.url()
+                ));
+            }
+            if let Err(err) = ChapterSelection::from_config(&novel_url.selection()) {
+                errors.push(format!("{} 的章节选择条件有误: {}", novel_url.url(), err));
+            }
+        }
+        let download = &self.download_config;
+        if download.concurrency == 0 {
+            warnings.push("concurrency 为 0，将按 1 处理".to_string());
+        }
+        if download.timeout_secs == 0 {
+            errors.push("timeout_secs 必须大于 0".to_string());
+        }
+        if download.retry.base_delay_ms > download.retry.max_delay_ms {
+            warnings.push("retry.base_delay_ms 大于 retry.max_delay_ms".to_string());
+        }
+        for rate_limit in [Some(&download.rate_limit), esj.rate_limit.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            if rate_limit.requests_per_second < 0.0 {
+                errors.push("rate_limit.requests_per_second 不能为负数".to_string());
+            }
+        }
+        if let Some(proxy) = &download.proxy {
+            if reqwest::Proxy::all(proxy).is_err() {
+                errors.push(format!("proxy 地址无法解析: {}", proxy));
+            }
+        }
+        let epub = &self.epub_config;
+        if let Some(name) = &epub.device_profile {
+            if !epub.device_profiles.contains_key(name) {
+                errors.push(format!("device_profiles 中没有名为 {} 的设备配置", name));
+            }
+        }
+        for (name, profile) in &epub.device_profiles {
+            if !(1..=100).contains(&profile.jpeg_quality) {
+                errors.push(format!(
+                    "设备配置 {} 的 jpeg_quality 必须在 1-100 之间",
+                    name
+                ));
+            }
+            if profile.max_width == 0 || profile.max_height == 0 {
+                errors.push(format!(
+                    "设备配置 {} 的 max_width/max_height 必须大于 0",
+                    name
+                ));
+            }
+        }
+        if let Err(err) = OutputTemplate::parse(&epub.output_template) {
+            errors.push(format!("output_template 有误: {}", err));
+        }
+        let filename = &self.filename_config;
+        let unsafe_value = |value: &str| {
+            value
+                .chars()
+                .any(|c| c == '/' || c == '\\' || c.is_control())
+        };
+        if filename.replacements.values().any(|to| unsafe_value(to))
+            || unsafe_value(&filename.replacement)
+        {
+            errors.push("filename_config 的替换内容不能包含路径分隔符或控制字符".to_string());
+        }
+        if !(1..=240).contains(&filename.max_bytes) {
+            errors.push("filename_config.max_bytes 必须在 1-240 之间".to_string());
+        }
+        (errors, warnings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let mut config = Config::default();
+        let (errors, warnings) = config.check();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(warnings.len(), 1);
+
+        config.esj_zone_config.esj_root_path = "./esjNovelGen".to_string();
+        config.esj_zone_config.esj_output_path = "./esjNovelOutput".to_string();
+        config.esj_zone_config.ews_key = "key".to_string();
+        config.esj_zone_config.ews_token = "token".to_string();
+        assert_eq!(config.check(), (vec![], vec![]));
+
+        config.esj_zone_config.esj_novel_urls = vec![NovelUrl::Url("not a url".to_string())];
+        config.download_config.proxy = Some("::".to_string());
+        let (errors, _) = config.check();
+        assert_eq!(errors.len(), 2);
+
+        config.download_config.proxy = None;
+        config.esj_zone_config.esj_novel_urls = vec![NovelUrl::WithSelection {
+            url: "https://www.esjzone.me/detail/1.html".to_string(),
+            selection: SelectionConfig {
+                title: Some("第(".to_string()),
+                ..SelectionConfig::default()
+            },
+        }];
+        let (errors, _) = config.check();
+        assert_eq!(errors.len(), 1);
+
+        config.esj_zone_config.esj_novel_urls = vec![];
+        config.epub_config.device_profile = Some("kindle".to_string());
+        assert_eq!(config.check(), (vec![], vec![]));
+        assert!(config.epub_config.device_profile().unwrap().grayscale);
+        config.epub_config.device_profile = Some("nook".to_string());
+        config
+            .epub_config
+            .device_profiles
+            .get_mut("kobo")
+            .unwrap()
+            .jpeg_quality = 0;
+        let (errors, _) = config.check();
+        assert_eq!(errors.len(), 2);
+
+        config.epub_config = EpubConfig::default();
+        config.epub_config.output_template = "{author}/{name}.epub".to_string();
+        config.filename_config.replacement = "/".to_string();
+        config.filename_config.max_bytes = 0;
+        let (errors, _) = config.check();
+        assert_eq!(errors.len(), 3);
+    }
+
+    #[test]
+    fn test_default_config_file() -> Result<()> {
+        let config = Config::default().load()?;
+        assert!(config.check().0.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_novel_urls() -> Result<()> {
+        let urls: Vec<NovelUrl> = serde_yaml::from_str(
+            r#"
+- "https://www.esjzone.me/detail/1.html"
+- url: "https://www.esjzone.me/detail/2.html"
+  chapters: "120-"
+  volumes: "3-5"
+"#,
+        )?;
+        assert_eq!(urls[0].url(), "https://www.esjzone.me/detail/1.html");
+        assert!(urls[0].selection().chapters.is_none());
+        assert_eq!(urls[1].url(), "https://www.esjzone.me/detail/2.html");
+        assert_eq!(urls[1].selection().chapters.as_deref(), Some("120-"));
+        assert_eq!(urls[1].selection().volumes.as_deref(), Some("3-5"));
+        assert!(urls[1].selection().title.is_none());
+        Ok(())
+    }
 }
@@ -1,6 +1,18 @@
-use once_cell::sync::Lazy;
+use anyhow::{anyhow, Result};
+use once_cell::sync::{Lazy, OnceCell};
 
 use super::{Config, Template};
-pub static CONFIG: Lazy<Config> = Lazy::new(|| Config::default().load().unwrap());
+
+static CONFIG_CELL: OnceCell<Config> = OnceCell::new();
+
+/// 使用命令行覆盖后的配置，须在首次访问 `CONFIG` 之前调用
+pub fn init_config(config: Config) -> Result<()> {
+    CONFIG_CELL
+        .set(config)
+        .map_err(|_| anyhow!("配置已经初始化"))
+}
+
+pub static CONFIG: Lazy<&Config> =
+    Lazy::new(|| CONFIG_CELL.get_or_init(|| Config::default().load().unwrap()));
 
 pub static TEMPLATE: Lazy<Template> = Lazy::new(|| Template::default().load().unwrap());
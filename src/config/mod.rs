@@ -1,6 +1,10 @@
+#[allow(clippy::module_inception)]
 mod config;
 mod global;
 mod template;
-pub use crate::config::config::Config;
+pub use crate::config::config::{
+    Config, DeviceProfile, DownloadConfig, FilenameConfig, HttpCacheConfig, NovelUrl, OutputFormat,
+    RateLimitConfig, RetryConfig, SelectionConfig,
+};
 pub use crate::config::global::*;
 pub use crate::config::template::Template;
@@ -2,29 +2,15 @@ use std::fs;
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use serde_yaml;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Template {
     pub toc_prefix: String,
     pub toc_verison: String,
     pub toc_xmlns: String,
-    pub toc_meta_content: String,
     pub toc_meta_name: String,
     pub episode_prefix: String,
-}
-
-impl Default for Template {
-    fn default() -> Self {
-        Template {
-            toc_prefix: String::new(),
-            toc_verison: String::new(),
-            toc_xmlns: String::new(),
-            toc_meta_content: String::new(),
-            toc_meta_name: String::new(),
-            episode_prefix: String::new(),
-        }
-    }
+    pub epub3_episode_prefix: String,
 }
 
 impl Template {
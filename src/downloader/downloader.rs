@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use reqwest::{header, Client, Method, Proxy, Response, StatusCode, Url};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::warn;
+
+use super::{HttpCache, RateLimiter, RetryPolicy};
+use crate::config::{DownloadConfig, RateLimitConfig};
+
+/// 整个运行期间共享的下载器，clone 后共用同一个连接池、cookie 与并发/限速状态
+#[derive(Clone)]
+pub struct Downloader {
+    pub client: Client,
+    pub retry: RetryPolicy,
+    pub rate_limit: RateLimitConfig,
+    concurrency: usize,
+    /// 每个站点各自的并发信号量，章节与插画下载共用
+    semaphores: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+    rate_limiter: Arc<RateLimiter>,
+    /// 未启用 HTTP 缓存时为空
+    http_cache: Option<Arc<HttpCache>>,
+}
+
+impl Downloader {
+    pub fn new(config: &DownloadConfig) -> Result<Self> {
+        let mut headers = header::HeaderMap::new();
+        headers.insert(
+            header::USER_AGENT,
+            header::HeaderValue::from_str(&config.user_agent)?,
+        );
+        let mut builder = reqwest::Client::builder()
+            .default_headers(headers)
+            .cookie_store(true)
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .timeout(Duration::from_secs(config.timeout_secs));
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?);
+        }
+        Ok(Downloader {
+            client: builder.build()?,
+            retry: RetryPolicy::from_config(&config.retry),
+            rate_limit: config.rate_limit.clone(),
+            concurrency: config.concurrency.max(1),
+            semaphores: Arc::new(Mutex::new(HashMap::new())),
+            rate_limiter: Arc::new(RateLimiter::new()),
+            http_cache: match config.http_cache.enabled {
+                true => Some(Arc::new(HttpCache::new(&config.http_cache))),
+                false => None,
+            },
+        })
+    }
+
+    /// 使用站点自己的限速配置，仍共用同一个连接池
+    pub fn with_rate_limit(&self, rate_limit: &RateLimitConfig) -> Self {
+        let mut downloader = self.clone();
+        downloader.rate_limit = rate_limit.clone();
+        downloader
+    }
+
+    async fn acquire(&self, host: &str) -> Result<OwnedSemaphorePermit> {
+        let semaphore = {
+            let mut semaphores = self.semaphores.lock().unwrap();
+            Arc::clone(
+                semaphores
+                    .entry(host.to_string())
+                    .or_insert_with(|| Arc::new(Semaphore::new(self.concurrency))),
+            )
+        };
+        Ok(semaphore.acquire_owned().await?)
+    }
+
+    pub async fn fetch(
+        &self,
+        method: Method,
+        url: &str,
+        cookie: Option<String>,
+        headers: header::HeaderMap,
+    ) -> reqwest::Result<Response> {
+        let mut request = self.client.request(method, url).headers(headers);
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie)
+        }
+        request.send().await
+    }
+
+    /// HTTP 缓存命中（收到 304）的次数
+    pub fn http_cache_hits(&self) -> Option<usize> {
+        self.http_cache.as_ref().map(|cache| cache.hits())
+    }
+
+    /// 在并发与限速限制内发送 GET 请求并读取响应体及 Content-Type，遇到暂时性错误按重试策略重试；
+    /// 启用 HTTP 缓存时发送条件请求，304 时返回缓存的内容。带有登录 cookie 的请求不使用 HTTP 缓存，
+    /// 避免登录前后或切换账号后读到其他身份的响应，也不把需要登录的内容写入磁盘
+    async fn fetch_with_retry(
+        &self,
+        url: &str,
+        cookie: Option<String>,
+    ) -> Result<(Vec<u8>, Option<String>)> {
+        let host = Url::parse(url)?.host_str().unwrap_or_default().to_string();
+        let http_cache = self.http_cache.as_ref().filter(|_| cookie.is_none());
+        let mut cached = match http_cache {
+            Some(http_cache) => http_cache.load(url).await,
+            None => None,
+        };
+        let headers = cached
+            .as_ref()
+            .map(|cached| cached.conditional_headers())
+            .unwrap_or_default();
+        let mut attempt = 0;
+        loop {
+            let permit = self.acquire(&host).await?;
+            self.rate_limiter.acquire(&host, &self.rate_limit).await;
+            let mut retry_after = None;
+            let err = match self
+                .fetch(Method::GET, url, cookie.clone(), headers.clone())
+                .await
+            {
+                Ok(response)
+                    if response.status() == StatusCode::NOT_MODIFIED && cached.is_some() =>
+                {
+                    let cached = cached.take().unwrap();
+                    if let Some(http_cache) = http_cache {
+                        http_cache.refresh(url, &cached, response.headers()).await;
+                    }
+                    return Ok((cached.body, cached.content_type));
+                }
+                Ok(response) if RetryPolicy::is_retryable_status(response.status()) => {
+                    retry_after = RetryPolicy::retry_after(&response);
+                    response.error_for_status().unwrap_err()
+                }
+                Ok(response) => {
+                    let response = response.error_for_status()?;
+                    let response_headers = response.headers().clone();
+                    match response.bytes().await {
+                        Ok(body) => {
+                            if let Some(http_cache) = http_cache {
+                                http_cache.store(url, &body, &response_headers).await;
+                            }
+                            let content_type = response_headers
+                                .get(header::CONTENT_TYPE)
+                                .and_then(|value| value.to_str().ok())
+                                .map(str::to_string);
+                            return Ok((body.to_vec(), content_type));
+                        }
+                        Err(err) if RetryPolicy::is_retryable_error(&err) => err,
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+                Err(err) if RetryPolicy::is_retryable_error(&err) => err,
+                Err(err) => return Err(err.into()),
+            };
+            drop(permit);
+            if attempt >= self.retry.max_retries {
+                return Err(err.into());
+            }
+            let delay = self.retry.delay(attempt, retry_after);
+            warn!(
+                "请求{}失败: {}，{}ms后进行第{}次重试",
+                url,
+                err,
+                delay.as_millis(),
+                attempt + 1
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// 在并发限制内获取页面文本
+    pub async fn fetch_text(&self, url: &str, cookie: Option<String>) -> Result<String> {
+        let (body, _) = self.fetch_with_retry(url, cookie).await?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// 在并发限制内获取二进制内容及响应的 Content-Type
+    pub async fn fetch_bytes(
+        &self,
+        url: &str,
+        cookie: Option<String>,
+    ) -> Result<(Vec<u8>, Option<String>)> {
+        self.fetch_with_retry(url, cookie).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::config::HttpCacheConfig;
+
+    /// 本地模拟服务器，按顺序返回预设的响应，最后一个响应重复使用
+    async fn mock_server(responses: Vec<&'static str>) -> Result<(String, Arc<AtomicUsize>)> {
+        let (url, hits, _) = recording_server(responses).await?;
+        Ok((url, hits))
+    }
+
+    /// 同 `mock_server`，另外记录收到的请求
+    async fn recording_server(
+        responses: Vec<&'static str>,
+    ) -> Result<(String, Arc<AtomicUsize>, Arc<Mutex<Vec<String>>>)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/", listener.local_addr()?);
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&hits);
+        let requests = Arc::new(Mutex::new(vec![]));
+        let recorded = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let idx = counter.fetch_add(1, Ordering::SeqCst);
+                let response = responses[idx.min(responses.len() - 1)];
+                let mut buf = [0u8; 4096];
+                let len = stream.read(&mut buf).await.unwrap_or(0);
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(&buf[..len]).to_string());
+                if response.is_empty() {
+                    tokio::spawn(async move {
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        drop(stream);
+                    });
+                    continue;
+                }
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            }
+        });
+        Ok((url, hits, requests))
+    }
+
+    fn test_downloader(max_retries: u32) -> Downloader {
+        let mut downloader = Downloader::new(&DownloadConfig {
+            timeout_secs: 1,
+            http_cache: HttpCacheConfig {
+                enabled: false,
+                ..HttpCacheConfig::default()
+            },
+            ..DownloadConfig::default()
+        })
+        .unwrap();
+        downloader.retry = RetryPolicy {
+            max_retries,
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(50),
+        };
+        downloader
+    }
+
+    const UNAVAILABLE: &str = "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const TOO_MANY: &str =
+        "HTTP/1.1 429 Too Many Requests\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const NOT_FOUND: &str =
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    /// 不返回任何内容，直到客户端超时
+    const HANG: &str = "";
+    const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+
+    #[tokio::test]
+    async fn test_host_concurrency_limit() -> Result<()> {
+        let downloader = test_downloader(0);
+        let limit = downloader.concurrency;
+        let mut permits = vec![];
+        for _ in 0..limit {
+            permits.push(downloader.acquire("limit.test").await?);
+        }
+        let blocked =
+            tokio::time::timeout(Duration::from_millis(50), downloader.acquire("limit.test")).await;
+        assert!(blocked.is_err());
+        // 其他站点不受影响
+        let _other = downloader.acquire("other.test").await?;
+        permits.pop();
+        let _permit = downloader.acquire("limit.test").await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_until_success() -> Result<()> {
+        let (url, hits) = mock_server(vec![UNAVAILABLE, TOO_MANY, OK]).await?;
+        let body = test_downloader(3).fetch_text(&url, None).await?;
+        assert_eq!(body, "ok");
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_exhausted() -> Result<()> {
+        let (url, hits) = mock_server(vec![UNAVAILABLE]).await?;
+        let res = test_downloader(2).fetch_bytes(&url, None).await;
+        assert!(res.is_err());
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_on_client_error() -> Result<()> {
+        let (url, hits) = mock_server(vec![NOT_FOUND, OK]).await?;
+        let res = test_downloader(3).fetch_text(&url, None).await;
+        assert!(res.is_err());
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_honour_retry_after() -> Result<()> {
+        let (url, _) = mock_server(vec![
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            OK,
+        ])
+        .await?;
+        let mut downloader = test_downloader(1);
+        downloader.retry.max_delay = Duration::from_secs(2);
+        let start = std::time::Instant::now();
+        let body = downloader.fetch_text(&url, None).await?;
+        assert_eq!(body, "ok");
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cap_retry_after() -> Result<()> {
+        let (url, _) = mock_server(vec![
+            "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 86400\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            OK,
+        ])
+        .await?;
+        let body = tokio::time::timeout(
+            Duration::from_secs(5),
+            test_downloader(1).fetch_text(&url, None),
+        )
+        .await??;
+        assert_eq!(body, "ok");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_on_timeout() -> Result<()> {
+        let (url, hits) = mock_server(vec![HANG, OK]).await?;
+        let body = test_downloader(2).fetch_text(&url, None).await?;
+        assert_eq!(body, "ok");
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shared_state_between_clones() -> Result<()> {
+        let downloader = test_downloader(0);
+        let limit = downloader.concurrency;
+        let esj = downloader.with_rate_limit(&RateLimitConfig::default());
+        let mut permits = vec![];
+        for _ in 0..limit {
+            permits.push(esj.acquire("shared.test").await?);
+        }
+        let blocked =
+            tokio::time::timeout(Duration::from_millis(50), downloader.acquire("shared.test"))
+                .await;
+        assert!(blocked.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_conditional_request() -> Result<()> {
+        let (url, hits, requests) = recording_server(vec![
+            "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Type: text/html\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+            "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n",
+        ])
+        .await?;
+        let path = std::env::temp_dir().join("ranobe-conditional-test");
+        let _ = tokio::fs::remove_dir_all(&path).await;
+        let mut downloader = test_downloader(0);
+        downloader.http_cache = Some(Arc::new(HttpCache::new(&HttpCacheConfig {
+            path: path.to_string_lossy().to_string(),
+            ..HttpCacheConfig::default()
+        })));
+        assert_eq!(downloader.fetch_text(&url, None).await?, "ok");
+        let (body, content_type) = downloader.fetch_bytes(&url, None).await?;
+        assert_eq!(body, b"ok");
+        assert_eq!(content_type.as_deref(), Some("text/html"));
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+        assert_eq!(downloader.http_cache_hits(), Some(1));
+        let requests = requests.lock().unwrap().clone();
+        assert!(!requests[0].to_lowercase().contains("if-none-match"));
+        assert!(requests[1].to_lowercase().contains("if-none-match: \"v1\""));
+        tokio::fs::remove_dir_all(&path).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_skip_http_cache_with_cookie() -> Result<()> {
+        let (url, hits, requests) = recording_server(vec![
+            "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+        ])
+        .await?;
+        let path = std::env::temp_dir().join("ranobe-cookie-cache-test");
+        let _ = tokio::fs::remove_dir_all(&path).await;
+        let mut downloader = test_downloader(0);
+        downloader.http_cache = Some(Arc::new(HttpCache::new(&HttpCacheConfig {
+            path: path.to_string_lossy().to_string(),
+            ..HttpCacheConfig::default()
+        })));
+        let cookie = Some("ews_key=key;ews_token=token;".to_string());
+        for _ in 0..2 {
+            assert_eq!(downloader.fetch_text(&url, cookie.clone()).await?, "ok");
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+        assert_eq!(downloader.http_cache_hits(), Some(0));
+        let requests = requests.lock().unwrap().clone();
+        assert!(!requests[1].to_lowercase().contains("if-none-match"));
+        assert!(!path.exists());
+        Ok(())
+    }
+}
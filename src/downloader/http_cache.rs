@@ -0,0 +1,267 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use md5::{Digest, Md5};
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::config::HttpCacheConfig;
+use crate::fs::atomic_write;
+
+/// 缓存条目的元数据，响应体单独保存为 `<hash>.body`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct EntryMeta {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    content_type: Option<String>,
+    /// 写入或最近一次 304 时的时间戳（微秒），超出大小上限时按此淘汰
+    stored_at: i64,
+}
+
+/// 从缓存中读取的响应
+pub struct CachedResponse {
+    pub body: Vec<u8>,
+    pub content_type: Option<String>,
+    meta: EntryMeta,
+}
+
+impl CachedResponse {
+    /// 条件请求头
+    pub fn conditional_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let validators = [
+            (header::IF_NONE_MATCH, &self.meta.etag),
+            (header::IF_MODIFIED_SINCE, &self.meta.last_modified),
+        ];
+        for (name, value) in validators {
+            if let Some(value) = value.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+                headers.insert(name, value);
+            }
+        }
+        headers
+    }
+}
+
+/// 磁盘上的 HTTP 响应缓存，只保存带有 ETag 或 Last-Modified 的响应
+pub struct HttpCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    /// 缓存中响应体的总大小，首次写入时统计
+    size: Mutex<Option<u64>>,
+    hits: AtomicUsize,
+}
+
+impl HttpCache {
+    pub fn new(config: &HttpCacheConfig) -> Self {
+        HttpCache {
+            dir: PathBuf::from(&config.path),
+            max_bytes: config.max_size_mb * 1024 * 1024,
+            size: Mutex::new(None),
+            hits: AtomicUsize::new(0),
+        }
+    }
+
+    /// 收到 304 的次数
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::SeqCst)
+    }
+
+    fn paths(&self, url: &str) -> (PathBuf, PathBuf) {
+        let mut hasher = Md5::new();
+        hasher.update(url);
+        let name = hex::encode(hasher.finalize());
+        (
+            self.dir.join(format!("{}.json", name)),
+            self.dir.join(format!("{}.body", name)),
+        )
+    }
+
+    pub async fn load(&self, url: &str) -> Option<CachedResponse> {
+        let (meta_path, body_path) = self.paths(url);
+        let meta: EntryMeta =
+            serde_json::from_str(&tokio::fs::read_to_string(meta_path).await.ok()?).ok()?;
+        // 不同地址的哈希相同时不使用
+        if meta.url != url {
+            return None;
+        }
+        let body = tokio::fs::read(body_path).await.ok()?;
+        Some(CachedResponse {
+            body,
+            content_type: meta.content_type.clone(),
+            meta,
+        })
+    }
+
+    /// 保存响应，没有 ETag 与 Last-Modified 时不缓存
+    pub async fn store(&self, url: &str, body: &[u8], headers: &HeaderMap) {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let meta = EntryMeta {
+            url: url.to_string(),
+            etag: header(header::ETAG),
+            last_modified: header(header::LAST_MODIFIED),
+            content_type: header(header::CONTENT_TYPE),
+            stored_at: chrono::Utc::now().timestamp_micros(),
+        };
+        if meta.etag.is_none() && meta.last_modified.is_none() {
+            return;
+        }
+        let mut size = self.size.lock().await;
+        let (meta_path, body_path) = self.paths(url);
+        let replaced = tokio::fs::metadata(&body_path)
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        let res = async {
+            atomic_write(&body_path, body).await?;
+            atomic_write(&meta_path, &serde_json::to_vec(&meta)?).await
+        }
+        .await;
+        if let Err(err) = res {
+            warn!("HTTP缓存{}写入失败: {}", url, err);
+            return;
+        }
+        let total = match *size {
+            Some(total) => total.saturating_sub(replaced) + body.len() as u64,
+            None => self.total_size().await,
+        };
+        *size = Some(match self.max_bytes > 0 && total > self.max_bytes {
+            true => self.evict(total).await,
+            false => total,
+        });
+    }
+
+    /// 收到 304 时更新缓存条目的验证信息与时间
+    pub async fn refresh(&self, url: &str, cached: &CachedResponse, headers: &HeaderMap) {
+        self.hits.fetch_add(1, Ordering::SeqCst);
+        debug!("HTTP缓存命中: {}", url);
+        let mut meta = cached.meta.clone();
+        for (name, value) in [
+            (header::ETAG, &mut meta.etag),
+            (header::LAST_MODIFIED, &mut meta.last_modified),
+        ] {
+            if let Some(new) = headers.get(name).and_then(|v| v.to_str().ok()) {
+                *value = Some(new.to_string());
+            }
+        }
+        meta.stored_at = chrono::Utc::now().timestamp_micros();
+        let (meta_path, _) = self.paths(url);
+        let res = async { atomic_write(&meta_path, &serde_json::to_vec(&meta)?).await }.await;
+        if let Err(err) = res {
+            warn!("HTTP缓存{}写入失败: {}", url, err);
+        }
+    }
+
+    /// 缓存中的全部条目：(写入时间, 元数据路径, 响应体路径, 响应体大小)
+    async fn entries(&self) -> Vec<(i64, PathBuf, PathBuf, u64)> {
+        let mut entries = vec![];
+        let Ok(mut dir) = tokio::fs::read_dir(&self.dir).await else {
+            return entries;
+        };
+        while let Ok(Some(entry)) = dir.next_entry().await {
+            let meta_path = entry.path();
+            if meta_path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let Ok(content) = tokio::fs::read_to_string(&meta_path).await else {
+                continue;
+            };
+            let stored_at = serde_json::from_str::<EntryMeta>(&content)
+                .map(|meta| meta.stored_at)
+                .unwrap_or_default();
+            let body_path = meta_path.with_extension("body");
+            let size = tokio::fs::metadata(&body_path)
+                .await
+                .map(|metadata| metadata.len())
+                .unwrap_or(0);
+            entries.push((stored_at, meta_path, body_path, size));
+        }
+        entries
+    }
+
+    async fn total_size(&self) -> u64 {
+        self.entries().await.iter().map(|entry| entry.3).sum()
+    }
+
+    /// 从最早写入的条目开始删除，直到总大小不超过上限，返回剩余的总大小
+    async fn evict(&self, mut total: u64) -> u64 {
+        let mut entries = self.entries().await;
+        entries.sort_by_key(|entry| entry.0);
+        for (_, meta_path, body_path, size) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            let _ = tokio::fs::remove_file(meta_path).await;
+            let _ = tokio::fs::remove_file(body_path).await;
+            total = total.saturating_sub(size);
+        }
+        debug!("HTTP缓存超出上限，清理后剩余{}字节", total);
+        total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn test_store_and_evict() -> Result<()> {
+        let dir = std::env::temp_dir().join("ranobe-http-cache-test");
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        let mut cache = HttpCache::new(&HttpCacheConfig {
+            enabled: true,
+            path: dir.to_string_lossy().to_string(),
+            max_size_mb: 0,
+        });
+        cache.max_bytes = 25;
+
+        let mut headers = HeaderMap::new();
+        cache
+            .store("https://mock.test/none", b"body", &headers)
+            .await;
+        assert!(cache.load("https://mock.test/none").await.is_none());
+
+        headers.insert(header::ETAG, HeaderValue::from_static("\"v1\""));
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
+        for idx in 1..=3 {
+            let url = format!("https://mock.test/{}", idx);
+            cache.store(&url, &[b'0' + idx; 10], &headers).await;
+        }
+        // 第三次写入后超出上限，最早的条目被删除
+        assert!(cache.load("https://mock.test/1").await.is_none());
+        let cached = cache.load("https://mock.test/3").await.unwrap();
+        assert_eq!(cached.body, b"3333333333");
+        assert_eq!(cached.content_type.as_deref(), Some("text/html"));
+        assert_eq!(
+            cached.conditional_headers()[header::IF_NONE_MATCH],
+            "\"v1\""
+        );
+        assert!(!cached
+            .conditional_headers()
+            .contains_key(header::IF_MODIFIED_SINCE));
+        assert_eq!(cache.total_size().await, 20);
+
+        // 304 刷新后成为最新的条目
+        let cached = cache.load("https://mock.test/2").await.unwrap();
+        cache
+            .refresh("https://mock.test/2", &cached, &HeaderMap::new())
+            .await;
+        assert_eq!(cache.hits(), 1);
+        cache
+            .store("https://mock.test/4", &[b'4'; 10], &headers)
+            .await;
+        assert!(cache.load("https://mock.test/2").await.is_some());
+        assert!(cache.load("https://mock.test/3").await.is_none());
+
+        tokio::fs::remove_dir_all(&dir).await?;
+        Ok(())
+    }
+}
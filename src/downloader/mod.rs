@@ -0,0 +1,10 @@
+#[allow(clippy::module_inception)]
+mod downloader;
+mod http_cache;
+mod rate_limit;
+mod retry;
+
+pub use crate::downloader::downloader::Downloader;
+pub use crate::downloader::http_cache::HttpCache;
+pub use crate::downloader::rate_limit::RateLimiter;
+pub use crate::downloader::retry::RetryPolicy;
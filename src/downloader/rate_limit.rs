@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimitConfig;
+
+/// 令牌桶：以固定速率补充令牌，允许不超过容量的突发请求
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    rate: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        let capacity = config.burst.max(1) as f64;
+        TokenBucket {
+            capacity,
+            tokens: capacity,
+            rate: config.requests_per_second,
+            last: Instant::now(),
+        }
+    }
+
+    /// 预订一个令牌，返回需要等待的时间。令牌可以透支，后来者按顺序排队等待
+    pub fn reserve(&mut self) -> Duration {
+        if self.rate <= 0.0 {
+            return Duration::ZERO;
+        }
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// 每个站点各自的令牌桶，章节、封面与插画请求共用
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, Arc<Mutex<TokenBucket>>>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        RateLimiter {
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 等待 host 对应的令牌桶放行，首次访问时按 config 创建令牌桶
+    pub async fn acquire(&self, host: &str, config: &RateLimitConfig) {
+        let bucket = {
+            let mut buckets = self.buckets.lock().unwrap();
+            Arc::clone(
+                buckets
+                    .entry(host.to_string())
+                    .or_insert_with(|| Arc::new(Mutex::new(TokenBucket::new(config)))),
+            )
+        };
+        let wait = bucket.lock().unwrap().reserve();
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket_burst() {
+        let mut bucket = TokenBucket::new(&RateLimitConfig {
+            requests_per_second: 10.0,
+            burst: 2,
+        });
+        assert_eq!(bucket.reserve(), Duration::ZERO);
+        assert_eq!(bucket.reserve(), Duration::ZERO);
+        let wait = bucket.reserve();
+        assert!(wait > Duration::from_millis(80) && wait <= Duration::from_millis(100));
+        let wait = bucket.reserve();
+        assert!(wait > Duration::from_millis(180) && wait <= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_token_bucket_unlimited() {
+        let mut bucket = TokenBucket::new(&RateLimitConfig {
+            requests_per_second: 0.0,
+            burst: 1,
+        });
+        for _ in 0..100 {
+            assert_eq!(bucket.reserve(), Duration::ZERO);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_per_host() {
+        let config = RateLimitConfig {
+            requests_per_second: 20.0,
+            burst: 1,
+        };
+        let limiter = RateLimiter::new();
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire("rate.test", &config).await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(90));
+        let start = Instant::now();
+        limiter.acquire("other-rate.test", &config).await;
+        assert!(start.elapsed() < Duration::from_millis(40));
+    }
+}
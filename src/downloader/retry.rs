@@ -0,0 +1,114 @@
+use std::time::{Duration, SystemTime};
+
+use rand::Rng;
+use reqwest::{header, Response, StatusCode};
+
+use crate::config::RetryConfig;
+
+/// 请求失败时的重试策略：指数退避加随机抖动
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &RetryConfig) -> Self {
+        RetryPolicy {
+            max_retries: config.max_retries,
+            base_delay: Duration::from_millis(config.base_delay_ms),
+            max_delay: Duration::from_millis(config.max_delay_ms),
+        }
+    }
+
+    /// 429 与 5xx 视为暂时性错误
+    pub fn is_retryable_status(status: StatusCode) -> bool {
+        status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+    }
+
+    /// 超时、连接失败以及读取响应体中断视为暂时性错误
+    pub fn is_retryable_error(err: &reqwest::Error) -> bool {
+        err.is_timeout() || err.is_connect() || err.is_request() || err.is_body()
+    }
+
+    /// 第 attempt 次重试前的等待时间，取指数退避上限的一半再加上随机抖动
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        let half = exp / 2;
+        let jitter = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+        half + Duration::from_millis(jitter)
+    }
+
+    /// 第 attempt 次重试前实际等待的时间：服务器要求的 `Retry-After` 较长时以其为准，
+    /// 但不超过 `max_delay`，避免异常的响应头让下载长时间停滞
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let retry_after = retry_after.unwrap_or_default().min(self.max_delay);
+        self.backoff(attempt).max(retry_after)
+    }
+
+    /// 解析 `Retry-After`，支持秒数与 HTTP 日期两种格式
+    pub fn retry_after(response: &Response) -> Option<Duration> {
+        let value = response
+            .headers()
+            .get(header::RETRY_AFTER)?
+            .to_str()
+            .ok()?
+            .trim();
+        if let Ok(secs) = value.parse::<u64>() {
+            return Some(Duration::from_secs(secs));
+        }
+        httpdate::parse_http_date(value)
+            .ok()?
+            .duration_since(SystemTime::now())
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_bounds() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+        };
+        for attempt in 0..8 {
+            let exp = Duration::from_millis(100 * 2u64.pow(attempt)).min(policy.max_delay);
+            let delay = policy.backoff(attempt);
+            assert!(delay >= exp / 2 && delay <= exp, "{attempt}: {delay:?}");
+        }
+    }
+
+    #[test]
+    fn test_retry_after_capped() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_secs(30),
+        };
+        let delay = policy.delay(0, Some(Duration::from_secs(5)));
+        assert_eq!(delay, Duration::from_secs(5));
+        let delay = policy.delay(0, Some(Duration::from_secs(86400)));
+        assert_eq!(delay, Duration::from_secs(30));
+        assert!(policy.delay(0, None) <= Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_retryable_status() {
+        assert!(RetryPolicy::is_retryable_status(
+            StatusCode::TOO_MANY_REQUESTS
+        ));
+        assert!(RetryPolicy::is_retryable_status(
+            StatusCode::SERVICE_UNAVAILABLE
+        ));
+        assert!(!RetryPolicy::is_retryable_status(StatusCode::NOT_FOUND));
+        assert!(!RetryPolicy::is_retryable_status(StatusCode::OK));
+    }
+}
@@ -0,0 +1,33 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+/// 先写入同目录下的临时文件再改名替换，进程中断时不会留下不完整的文件
+pub async fn atomic_write(path: &Path, content: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    tokio::fs::write(&tmp, content).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_atomic_write() -> Result<()> {
+        let dir = std::env::temp_dir().join("ranobe-atomic-write-test");
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        let path = dir.join("a").join("b.json");
+        atomic_write(&path, b"1").await?;
+        atomic_write(&path, b"2").await?;
+        assert_eq!(tokio::fs::read(&path).await?, b"2");
+        assert!(!dir.join("a").join("b.json.tmp").exists());
+        tokio::fs::remove_dir_all(&dir).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::book::ChapterRecord;
+use crate::fs::atomic_write;
+use crate::CONFIG;
+
+/// 书库中的一本小说
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LibraryEntry {
+    pub url: String,
+    pub source: String,
+    pub title: String,
+    pub author: String,
+    pub chapters: Vec<ChapterRecord>,
+    pub illustrations: HashMap<String, String>,
+    pub save_path: PathBuf,
+    pub output_file: PathBuf,
+    /// 分卷输出时各卷的 epub
+    #[serde(default)]
+    pub volume_files: Vec<PathBuf>,
+    pub first_downloaded: DateTime<Utc>,
+    pub last_checked: DateTime<Utc>,
+}
+
+/// 记录所有已下载小说的书库，保存为小说生成目录下的 `library.json`
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Library {
+    #[serde(skip)]
+    path: PathBuf,
+    pub books: Vec<LibraryEntry>,
+}
+
+impl Library {
+    const FILE_NAME: &'static str = "library.json";
+
+    pub fn default_path() -> PathBuf {
+        Library::path(Path::new(&CONFIG.esj_zone_config.esj_root_path))
+    }
+
+    /// 小说生成目录 `root` 下的书库文件
+    pub fn path(root: &Path) -> PathBuf {
+        root.join(Library::FILE_NAME)
+    }
+
+    /// 读取书库，文件不存在时返回空书库
+    pub async fn load(path: &Path) -> Result<Self> {
+        let mut library = match tokio::fs::read_to_string(path).await {
+            Ok(content) => serde_json::from_str(&content)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Library::default(),
+            Err(err) => return Err(err.into()),
+        };
+        library.path = path.to_path_buf();
+        Ok(library)
+    }
+
+    pub async fn save(&self) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+        atomic_write(&self.path, content.as_bytes()).await
+    }
+
+    pub fn get(&self, url: &str) -> Option<&LibraryEntry> {
+        self.books.iter().find(|book| book.url == url)
+    }
+
+    /// 新增或覆盖记录，保留首次下载时间
+    pub fn upsert(&mut self, mut entry: LibraryEntry) {
+        match self.books.iter_mut().find(|book| book.url == entry.url) {
+            Some(book) => {
+                entry.first_downloaded = book.first_downloaded;
+                *book = entry;
+            }
+            None => self.books.push(entry),
+        }
+    }
+
+    pub fn remove(&mut self, url: &str) -> Option<LibraryEntry> {
+        let idx = self.books.iter().position(|book| book.url == url)?;
+        Some(self.books.remove(idx))
+    }
+}
+
+impl LibraryEntry {
+    pub fn print_summary(&self) {
+        println!(
+            "《{}》 {} | {}章 | 最后检查: {} | {}",
+            self.title,
+            self.author,
+            self.chapters.len(),
+            self.last_checked
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M"),
+            self.url
+        );
+    }
+
+    pub fn print(&self) {
+        println!("书名: {}", self.title);
+        println!("作者: {}", self.author);
+        println!("来源: {} ({})", self.url, self.source);
+        println!("目录: {}", self.save_path.display());
+        match self.volume_files.is_empty() {
+            true => println!("输出: {}", self.output_file.display()),
+            false => {
+                for volume_file in &self.volume_files {
+                    println!("输出: {}", volume_file.display());
+                }
+            }
+        }
+        println!(
+            "首次下载: {}",
+            self.first_downloaded
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+        );
+        println!(
+            "最后检查: {}",
+            self.last_checked
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+        );
+        println!("插画: {}张", self.illustrations.len());
+        println!("章节: {}章", self.chapters.len());
+        for (idx, chapter) in self.chapters.iter().enumerate() {
+            println!(
+                "  {:>4}. {} [{}]",
+                idx + 1,
+                chapter.title,
+                chapter.hash.get(..8).unwrap_or(&chapter.hash)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(url: &str, title: &str, checked: DateTime<Utc>) -> LibraryEntry {
+        LibraryEntry {
+            url: url.to_string(),
+            source: "esjzone".to_string(),
+            title: title.to_string(),
+            author: "野兽先生".to_string(),
+            chapters: vec![ChapterRecord {
+                url: format!("{}#1", url),
+                title: "第一章".to_string(),
+                hash: "d41d8cd98f00b204e9800998ecf8427e".to_string(),
+            }],
+            illustrations: HashMap::new(),
+            save_path: PathBuf::from("./esjNovelGen").join(title),
+            output_file: PathBuf::from("./esjNovelOutput").join(format!("{}.epub", title)),
+            volume_files: vec![],
+            first_downloaded: checked,
+            last_checked: checked,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_library_round_trip() -> Result<()> {
+        let dir = std::env::temp_dir().join("ranobe-library-round-trip-test");
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        let path = Library::path(&dir);
+        let mut library = Library::load(&path).await?;
+        assert!(library.books.is_empty());
+
+        let first = Utc::now() - chrono::Duration::days(7);
+        library.upsert(entry("https://mock.test/1.html", "下北泽秘闻", first));
+        library.upsert(entry("https://mock.test/2.html", "真夏夜之梦", first));
+        library.upsert(entry("https://mock.test/1.html", "下北泽秘闻", Utc::now()));
+        library.save().await?;
+
+        let mut library = Library::load(&path).await?;
+        assert_eq!(library.books.len(), 2);
+        let book = library.get("https://mock.test/1.html").unwrap();
+        assert_eq!(book.first_downloaded, first);
+        assert!(book.last_checked > first);
+
+        assert!(library.remove("https://mock.test/2.html").is_some());
+        assert!(library.remove("https://mock.test/2.html").is_none());
+        library.save().await?;
+        assert_eq!(Library::load(&path).await?.books.len(), 1);
+        tokio::fs::remove_dir_all(&dir).await?;
+        Ok(())
+    }
+}
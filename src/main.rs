@@ -1,27 +1,23 @@
-use std::sync::Arc;
-
 use anyhow::Result;
-use book::Book;
+use clap::Parser;
+use cli::Cli;
 use config::CONFIG;
-use downloader::Credential;
 use once_cell::sync::Lazy;
 mod book;
+mod cli;
 mod config;
 mod downloader;
+mod fs;
+mod library;
+mod source;
+mod validator;
 use crate::downloader::Downloader;
-use tracing_subscriber;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
+    let cli = Cli::parse();
+    config::init_config(cli.load_config()?)?;
     Lazy::force(&CONFIG);
-    let esj_credential = Arc::new(Credential {
-        esj_key: CONFIG.esj_zone_config.ews_key.clone(),
-        esj_token: CONFIG.esj_zone_config.ews_token.clone(),
-    });
-    for esj_url in &CONFIG.esj_zone_config.esj_novel_urls {
-        let credential = Arc::clone(&esj_credential);
-        Book::gen_epub(&esj_url, Some(&credential)).await?;
-    }
-    Ok(())
+    cli.run().await
 }
@@ -0,0 +1,389 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::Url;
+use scraper::{selectable::Selectable, ElementRef, Html, Selector};
+
+use super::{BookInfo, BookMeta, ChapterRef, Illustration, Source};
+use crate::book::Episode;
+use crate::{Downloader, CONFIG};
+
+pub struct Credential {
+    pub esj_key: String,
+    pub esj_token: String,
+}
+
+pub struct EsjZone {
+    downloader: Downloader,
+    credential: Option<Arc<Credential>>,
+}
+
+impl EsjZone {
+    /// 使用 `esj_zone_config` 中的 `ews_key` 与 `ews_token` 登录
+    pub fn from_config(downloader: &Downloader) -> Self {
+        let config = &CONFIG.esj_zone_config;
+        let credential = (!config.ews_key.is_empty() && !config.ews_token.is_empty()).then(|| {
+            Arc::new(Credential {
+                esj_key: config.ews_key.clone(),
+                esj_token: config.ews_token.clone(),
+            })
+        });
+        EsjZone::new(downloader, credential)
+    }
+
+    pub fn new(downloader: &Downloader, credential: Option<Arc<Credential>>) -> Self {
+        let downloader = match &CONFIG.esj_zone_config.rate_limit {
+            Some(rate_limit) => downloader.with_rate_limit(rate_limit),
+            None => downloader.clone(),
+        };
+        EsjZone {
+            downloader,
+            credential,
+        }
+    }
+
+    /// 未设置凭据时不发送 cookie，以便匿名请求可以使用 HTTP 缓存
+    fn cookie(&self) -> Option<String> {
+        self.credential.as_ref().map(|credential| {
+            format!(
+                "ews_key={};ews_token={};",
+                credential.esj_key, credential.esj_token
+            )
+        })
+    }
+
+    async fn fetch_page(&self, url: &str) -> Result<String> {
+        self.downloader.fetch_text(url, self.cookie()).await
+    }
+
+    fn parse_book_meta(body: &str) -> BookMeta {
+        let doc = Html::parse_document(body);
+        let title_selector = Selector::parse(r#"h2[class="p-t-10 text-normal"]"#)
+            .expect("Failed to parse title selector");
+        let author_selector = Selector::parse(r#"ul[class="list-unstyled mb-2 book-detail"]"#)
+            .expect("Failed to parse title selector");
+        let episode_list_selector = Selector::parse(r#"div[id="chapterList"]"#)
+            .expect("Failed to parse episode list selector");
+        let a_selector = Selector::parse("a").expect("Failed to parse a tag selector");
+        let cover_selector = Selector::parse(r#"div[class="product-gallery text-center mb-3"]"#)
+            .expect("Failed to parse cover selector");
+
+        let title = doc
+            .select(&title_selector)
+            .next()
+            .map(|title| title.text().collect::<String>())
+            .unwrap_or_default();
+
+        let cover_url = doc
+            .select(&cover_selector)
+            .flat_map(|a_elem| a_elem.select(&a_selector))
+            .next()
+            .and_then(|a| a.value().attr("href"))
+            .map(str::to_string);
+
+        let author = doc
+            .select(&author_selector)
+            .flat_map(|ul_elem| ul_elem.select(&a_selector))
+            .next()
+            .map(|author| author.text().collect::<String>())
+            .unwrap_or_default();
+
+        let updated = EsjZone::detail_field(&doc, "更新日期");
+        let info = EsjZone::parse_book_info(&doc);
+
+        let mut chapters = vec![];
+        for chapter_list in doc.select(&episode_list_selector) {
+            EsjZone::parse_chapter_list(chapter_list, &mut None, &mut chapters);
+        }
+
+        BookMeta {
+            title,
+            author,
+            cover_url,
+            updated,
+            info,
+            chapters,
+        }
+    }
+
+    /// 详情页中的类型、连载状态、字数、标签与简介
+    fn parse_book_info(doc: &Html) -> BookInfo {
+        let tag_selector = Selector::parse(".widget-tags a").expect("Failed to parse tag selector");
+        let description_selector =
+            Selector::parse(".description").expect("Failed to parse description selector");
+        let p_selector = Selector::parse("p").expect("Failed to parse p tag selector");
+
+        let mut tags = vec![];
+        for tag in doc.select(&tag_selector) {
+            let tag = tag.text().collect::<String>().trim().to_string();
+            if !tag.is_empty() && !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+
+        let synopsis = doc.select(&description_selector).next().map(|description| {
+            let paragraphs: Vec<_> = description.select(&p_selector).collect();
+            let lines: Vec<String> = match paragraphs.is_empty() {
+                true => vec![description.text().collect::<String>()],
+                false => paragraphs
+                    .into_iter()
+                    .map(|p| p.text().collect::<String>())
+                    .collect(),
+            };
+            lines
+                .iter()
+                .flat_map(|line| line.lines())
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n")
+        });
+
+        BookInfo {
+            kind: EsjZone::detail_field(doc, "類型"),
+            status: EsjZone::detail_field(doc, "狀態")
+                .or_else(|| EsjZone::detail_field(doc, "連載狀態")),
+            tags,
+            // 字數顯示為 `123,456` 的形式
+            word_count: EsjZone::detail_field(doc, "字數")
+                .map(|count| count.replace(',', ""))
+                .and_then(|count| count.parse().ok()),
+            synopsis: synopsis.filter(|synopsis| !synopsis.is_empty()),
+        }
+    }
+
+    /// 详情页信息列表中 `<strong>名称:</strong> 值` 形式的一项
+    fn detail_field(doc: &Html, name: &str) -> Option<String> {
+        let li_selector = Selector::parse(r#"ul[class="list-unstyled mb-2 book-detail"] li"#)
+            .expect("Failed to parse detail selector");
+        doc.select(&li_selector)
+            .map(|li| li.text().collect::<String>())
+            .find_map(|text| {
+                let (label, value) = text.split_once([':', '：'])?;
+                (label.trim() == name).then(|| value.trim().to_string())
+            })
+            .filter(|value| !value.is_empty())
+    }
+
+    /// 按文档顺序遍历章节列表，`<details>` 的 `<summary>` 以及不含链接的 `<p>`/`<h*>` 视为卷标题
+    fn parse_chapter_list(
+        elem: ElementRef,
+        volume: &mut Option<String>,
+        chapters: &mut Vec<ChapterRef>,
+    ) {
+        let a_selector = Selector::parse("a").expect("Failed to parse a tag selector");
+        let summary_selector =
+            Selector::parse("summary").expect("Failed to parse summary selector");
+        for child in elem.children().filter_map(ElementRef::wrap) {
+            match child.value().name() {
+                "a" => {
+                    if let Some(url) = child.value().attr("href") {
+                        chapters.push(ChapterRef {
+                            title: child.text().collect::<String>().trim().to_string(),
+                            url: url.to_string(),
+                            volume: volume.clone(),
+                        });
+                    }
+                }
+                "details" => {
+                    let mut group_volume = child
+                        .select(&summary_selector)
+                        .next()
+                        .map(|summary| summary.text().collect::<String>().trim().to_string())
+                        .filter(|summary| !summary.is_empty())
+                        .or_else(|| volume.clone());
+                    EsjZone::parse_chapter_list(child, &mut group_volume, chapters);
+                }
+                "summary" => {}
+                "p" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6"
+                    if child.select(&a_selector).next().is_none() =>
+                {
+                    let heading = child.text().collect::<String>().trim().to_string();
+                    if !heading.is_empty() {
+                        *volume = Some(heading);
+                    }
+                }
+                _ => EsjZone::parse_chapter_list(child, volume, chapters),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Source for EsjZone {
+    fn name(&self) -> &'static str {
+        "esjzone"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "ESJ Zone"
+    }
+
+    fn matches(&self, url: &str) -> bool {
+        Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(|host| host.contains("esjzone.")))
+            .unwrap_or(false)
+    }
+
+    async fn fetch_book_page(&self, url: &str) -> Result<String> {
+        self.fetch_page(url).await
+    }
+
+    fn parse_book_page(&self, page: &str) -> Result<BookMeta> {
+        Ok(EsjZone::parse_book_meta(page))
+    }
+
+    async fn fetch_episode_page(&self, url: &str) -> Result<String> {
+        self.fetch_page(url).await
+    }
+
+    fn parse_episode_page(&self, page: &str, url: &str, order: u32) -> Result<Episode> {
+        let doc = Html::parse_document(page);
+
+        let episode_title_selector = Selector::parse(r#"div[class="col-xl-9 col-lg-8 p-r-30"]"#)
+            .expect("Failed to parse episode title selector");
+        let h2_selector = Selector::parse("h2").expect("Failed to parse h2 tag selector");
+        let content_block_selector = Selector::parse(r#"div[class="forum-content mt-3"]"#)
+            .expect("Failed to parse content blocker selector");
+        let episode_title = doc
+            .select(&episode_title_selector)
+            .flat_map(|episode_title_elem| episode_title_elem.select(&h2_selector))
+            .map(|h2_elem| h2_elem.text().collect::<String>())
+            .next()
+            .unwrap_or_default();
+
+        let content = doc
+            .select(&content_block_selector)
+            .next()
+            .map(|content_block_elem| content_block_elem.html())
+            .unwrap_or_else(|| format!("{}: 无文本内容", episode_title));
+
+        let episode_save_path = format!("Text/{}.xhtml", order);
+        Ok(Episode {
+            episode_title,
+            content,
+            episode_save_path,
+            order,
+            url: url.to_string(),
+            volume: None,
+        })
+    }
+
+    fn resolve_illustrations(&self, content: &str) -> Vec<String> {
+        let content_selector = Selector::parse(r#"div[class="forum-content mt-3"]"#)
+            .expect("Failed to parse content selector");
+        let img_selector = Selector::parse("img").expect("Failed to parse image selector");
+        let doc = Html::parse_document(content);
+        doc.select(&content_selector)
+            .flat_map(|content| content.select(&img_selector))
+            .filter_map(|img| img.value().attr("src").map(str::to_string))
+            .collect()
+    }
+
+    async fn fetch_illustration(&self, url: &str) -> Result<Illustration> {
+        let (content, content_type) = self.downloader.fetch_bytes(url, None).await?;
+        Ok(Illustration {
+            content,
+            content_type,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use anyhow::Ok;
+
+    use super::*;
+    #[tokio::test]
+    async fn fuck_episodes() -> Result<()> {
+        let downloader = Downloader::new(&CONFIG.download_config)?;
+        let source = EsjZone::from_config(&downloader);
+        let url = "https://www.esjzone.me/forum/1719148048/225492.html";
+        let page = source.fetch_episode_page(url).await?;
+        let _ = source.parse_episode_page(&page, url, 1)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_illustrations() -> Result<()> {
+        let downloader = Downloader::new(&CONFIG.download_config)?;
+        let source = EsjZone::new(&downloader, None);
+        let content = r#"<div class="forum-content mt-3"><p>text</p><img src="https://example.com/a.jpg"><p><img src="https://example.com/b.png"></p></div>"#;
+        assert_eq!(
+            source.resolve_illustrations(content),
+            vec![
+                "https://example.com/a.jpg".to_string(),
+                "https://example.com/b.png".to_string()
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_book_meta() {
+        let body = r#"<html><body>
+<div class="product-gallery text-center mb-3"><a href="https://example.com/cover.jpg"><img></a></div>
+<h2 class="p-t-10 text-normal">下北泽秘闻</h2>
+<ul class="list-unstyled mb-2 book-detail">
+  <li><strong>類型:</strong> 日本輕小說</li>
+  <li><strong>狀態:</strong> 連載中</li>
+  <li><strong>作者:</strong> <a href="/tags/野兽先生/">野兽先生</a></li>
+  <li><strong>更新日期:</strong> 2024-05-30</li>
+  <li><strong>字數:</strong> <span id="txt">114,514</span></li>
+</ul>
+<section class="widget-tags m-t-20"><a class="tag" href="/tags/戀愛/">戀愛</a><a class="tag" href="/tags/校園/">校園</a><a class="tag" href="/tags/戀愛/">戀愛</a></section>
+<div class="book-description"><div class="description"><p>第一段 &amp; 简介</p><p> </p><p>第二段</p></div></div>
+<div id="chapterList">
+  <a href="https://www.esjzone.me/forum/1/0.html" target="_blank"><p>简介</p></a>
+  <p class="non">第一卷</p>
+  <a href="https://www.esjzone.me/forum/1/1.html" target="_blank"><p>第一章</p></a>
+  <a href="https://www.esjzone.me/forum/1/2.html" target="_blank"><p>第二章</p></a>
+  <details open>
+    <summary><strong>第二卷</strong></summary>
+    <a href="https://www.esjzone.me/forum/1/3.html" target="_blank"><p>第三章</p></a>
+  </details>
+  <details>
+    <summary>第三卷</summary>
+    <a href="https://www.esjzone.me/forum/1/4.html" target="_blank"><p>第四章</p></a>
+  </details>
+</div>
+</body></html>"#;
+        let meta = EsjZone::parse_book_meta(body);
+        assert_eq!(meta.title, "下北泽秘闻");
+        assert_eq!(meta.author, "野兽先生");
+        assert_eq!(
+            meta.cover_url.as_deref(),
+            Some("https://example.com/cover.jpg")
+        );
+        assert_eq!(meta.updated.as_deref(), Some("2024-05-30"));
+        assert_eq!(
+            meta.info,
+            BookInfo {
+                kind: Some("日本輕小說".to_string()),
+                status: Some("連載中".to_string()),
+                tags: vec!["戀愛".to_string(), "校園".to_string()],
+                word_count: Some(114514),
+                synopsis: Some("第一段 & 简介\n第二段".to_string()),
+            }
+        );
+        let chapters: Vec<_> = meta
+            .chapters
+            .iter()
+            .map(|chapter| (chapter.title.as_str(), chapter.volume.as_deref()))
+            .collect();
+        assert_eq!(
+            chapters,
+            vec![
+                ("简介", None),
+                ("第一章", Some("第一卷")),
+                ("第二章", Some("第一卷")),
+                ("第三章", Some("第二卷")),
+                ("第四章", Some("第三卷")),
+            ]
+        );
+    }
+}
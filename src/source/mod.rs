@@ -0,0 +1,105 @@
+mod esjzone;
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::book::Episode;
+pub use crate::source::esjzone::EsjZone;
+use crate::Downloader;
+
+/// 目录页中的一个章节链接
+#[derive(Clone)]
+pub struct ChapterRef {
+    pub title: String,
+    pub url: String,
+    /// 所属卷的标题，站点没有分卷时为 None
+    pub volume: Option<String>,
+}
+
+/// 小说详情页中解析出的元数据与章节列表
+pub struct BookMeta {
+    pub title: String,
+    pub author: String,
+    pub cover_url: Option<String>,
+    /// 站点显示的最后更新日期
+    pub updated: Option<String>,
+    pub info: BookInfo,
+    pub chapters: Vec<ChapterRef>,
+}
+
+/// 详情页中的类型、连载状态、标签、字数与简介，站点没有提供的项为空
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq)]
+#[serde(default)]
+pub struct BookInfo {
+    /// 类型，如「日本輕小說」
+    pub kind: Option<String>,
+    /// 连载状态，如「連載中」「已完結」
+    pub status: Option<String>,
+    pub tags: Vec<String>,
+    pub word_count: Option<u64>,
+    /// 简介的纯文本，每段一行
+    pub synopsis: Option<String>,
+}
+
+/// 下载的插画内容，`content_type` 为响应头中的 Content-Type
+pub struct Illustration {
+    pub content: Vec<u8>,
+    pub content_type: Option<String>,
+}
+
+/// 小说站点的抽象，新增站点只需实现该 trait
+#[async_trait]
+pub trait Source: Send + Sync {
+    /// 站点名称，用于日志输出
+    fn name(&self) -> &'static str;
+
+    /// 站点的显示名称，作为出版者写入 epub
+    fn display_name(&self) -> &'static str;
+
+    /// 判断该 url 是否属于本站点
+    fn matches(&self, url: &str) -> bool;
+
+    /// 获取详情页的原始 html
+    async fn fetch_book_page(&self, url: &str) -> Result<String>;
+
+    /// 从详情页解析书名、作者、封面以及章节列表
+    fn parse_book_page(&self, page: &str) -> Result<BookMeta>;
+
+    /// 获取章节页面的原始 html
+    async fn fetch_episode_page(&self, url: &str) -> Result<String>;
+
+    /// 从章节页面解析标题与正文
+    fn parse_episode_page(&self, page: &str, url: &str, order: u32) -> Result<Episode>;
+
+    /// 从章节正文中解析出插画地址
+    fn resolve_illustrations(&self, content: &str) -> Vec<String>;
+
+    /// 下载插画或封面
+    async fn fetch_illustration(&self, url: &str) -> Result<Illustration>;
+}
+
+/// 根据 url 选择对应的站点实现，各站点从自己的配置中读取登录凭据
+pub fn from_url(url: &str, downloader: &Downloader) -> Option<Arc<dyn Source>> {
+    let sources: Vec<Arc<dyn Source>> = vec![Arc::new(EsjZone::from_config(downloader))];
+    sources.into_iter().find(|source| source.matches(url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CONFIG;
+
+    #[test]
+    fn test_from_url() -> Result<()> {
+        let downloader = Downloader::new(&CONFIG.download_config)?;
+        let source = from_url("https://www.esjzone.me/detail/1719148048.html", &downloader);
+        assert_eq!(source.map(|source| source.name()), Some("esjzone"));
+        let source = from_url("https://www.esjzone.cc/detail/1718674070.html", &downloader);
+        assert_eq!(source.map(|source| source.name()), Some("esjzone"));
+        assert!(from_url("https://example.com/detail/1.html", &downloader).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,528 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use anyhow::Result;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use zip::ZipArchive;
+
+/// epub 结构检查的结果
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+struct ManifestItem {
+    id: String,
+    path: String,
+    media_type: String,
+    properties: String,
+}
+
+/// 检查磁盘上的 epub 文件
+pub fn validate(path: &Path) -> Result<ValidationReport> {
+    validate_archive(std::fs::File::open(path)?)
+}
+
+/// 检查 epub 的容器与包文档结构，无法作为 zip 打开时返回错误
+pub fn validate_archive<R: Read + Seek>(reader: R) -> Result<ValidationReport> {
+    let mut archive = ZipArchive::new(reader)?;
+    let mut report = ValidationReport::default();
+    check_mimetype(&mut archive, &mut report)?;
+
+    let files: HashSet<String> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .map(str::to_string)
+        .collect();
+    let Some(container) = read_file(&mut archive, "META-INF/container.xml")? else {
+        report
+            .errors
+            .push("缺少 META-INF/container.xml".to_string());
+        return Ok(report);
+    };
+    let rootfiles = match attribute_values(&container, "rootfile", "full-path") {
+        Ok(rootfiles) => rootfiles,
+        Err(err) => {
+            report
+                .errors
+                .push(format!("META-INF/container.xml 格式错误: {}", err));
+            return Ok(report);
+        }
+    };
+    let Some(opf_path) = rootfiles.into_iter().next() else {
+        report
+            .errors
+            .push("META-INF/container.xml 中没有 rootfile".to_string());
+        return Ok(report);
+    };
+    let Some(opf) = read_file(&mut archive, &opf_path)? else {
+        report
+            .errors
+            .push(format!("container.xml 指向的包文档 {} 不存在", opf_path));
+        return Ok(report);
+    };
+    let (version, items, spine, toc) = match parse_opf(&opf, &opf_path) {
+        Ok(package) => package,
+        Err(err) => {
+            report
+                .errors
+                .push(format!("包文档 {} 格式错误: {}", opf_path, err));
+            return Ok(report);
+        }
+    };
+
+    let mut ids = HashMap::new();
+    for item in &items {
+        if !is_xml_id(&item.id) {
+            report
+                .errors
+                .push(format!("manifest 中的 id {} 不是合法的 XML ID", item.id));
+        }
+        if ids.insert(item.id.as_str(), item).is_some() {
+            report
+                .errors
+                .push(format!("manifest 中的 id {} 重复", item.id));
+        }
+        if !files.contains(&item.path) {
+            report
+                .errors
+                .push(format!("manifest 中的 {} 不存在: {}", item.id, item.path));
+        }
+    }
+    if spine.is_empty() {
+        report.errors.push("spine 为空".to_string());
+    }
+    for idref in &spine {
+        if !ids.contains_key(idref.as_str()) {
+            report
+                .errors
+                .push(format!("spine 引用了 manifest 中不存在的 id: {}", idref));
+        }
+    }
+    match toc {
+        Some(toc) if !ids.contains_key(toc.as_str()) => report
+            .errors
+            .push(format!("spine 的 toc 引用了不存在的 id: {}", toc)),
+        None if !version.starts_with('3') => report.warnings.push("spine 未指定 toc".to_string()),
+        _ => {}
+    }
+    if version.starts_with('3')
+        && !items
+            .iter()
+            .any(|item| item.properties.split_whitespace().any(|p| p == "nav"))
+    {
+        report
+            .errors
+            .push("EPUB 3 的 manifest 中没有 properties=\"nav\" 的导航文档".to_string());
+    }
+
+    let declared: HashSet<&str> = items.iter().map(|item| item.path.as_str()).collect();
+    for item in items.iter().filter(|item| is_xml(item)) {
+        let Some(content) = read_file(&mut archive, &item.path)? else {
+            continue;
+        };
+        if let Err(err) = check_well_formed(&content) {
+            report
+                .errors
+                .push(format!("{} 不是格式正确的 XML: {}", item.path, err));
+            continue;
+        }
+        // 图片必须打包在 epub 中，远程地址与未声明的文件在阅读器中都无法显示
+        let base = item.path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        for src in attribute_values(&content, "img", "src")? {
+            if !declared.contains(resolve(base, &src).as_str()) {
+                report
+                    .errors
+                    .push(format!("{} 中的图片 {} 不在 manifest 中", item.path, src));
+            }
+        }
+    }
+
+    let mut orphans: Vec<_> = files
+        .iter()
+        .filter(|name| {
+            *name != "mimetype"
+                && !name.starts_with("META-INF/")
+                && **name != opf_path
+                && !declared.contains(name.as_str())
+        })
+        .collect();
+    orphans.sort();
+    for orphan in orphans {
+        report
+            .warnings
+            .push(format!("{} 未在 manifest 中声明", orphan));
+    }
+    Ok(report)
+}
+
+fn check_mimetype<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    report: &mut ValidationReport,
+) -> Result<()> {
+    if archive.is_empty() {
+        report.errors.push("压缩包为空".to_string());
+        return Ok(());
+    }
+    let mut first = archive.by_index(0)?;
+    if first.name() != "mimetype" {
+        report
+            .errors
+            .push(format!("第一个文件应为 mimetype，实际为 {}", first.name()));
+        return Ok(());
+    }
+    if first.compression() != zip::CompressionMethod::Stored {
+        report.errors.push("mimetype 不应压缩".to_string());
+    }
+    if first.extra_data().is_some_and(|extra| !extra.is_empty()) {
+        report.warnings.push("mimetype 带有额外字段".to_string());
+    }
+    let mut content = String::new();
+    first.read_to_string(&mut content)?;
+    if content != "application/epub+zip" {
+        report.errors.push(format!(
+            "mimetype 内容应为 application/epub+zip，实际为 {:?}",
+            content
+        ));
+    }
+    Ok(())
+}
+
+fn read_file<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Result<Option<String>> {
+    let mut file = match archive.by_name(name) {
+        Ok(file) => file,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    Ok(Some(content))
+}
+
+/// XML ID 必须以字母或 `_` 开头，只能包含字母、数字、`-`、`_` 与 `.`
+fn is_xml_id(id: &str) -> bool {
+    let mut chars = id.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_alphabetic() || first == '_')
+        && chars.all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+fn is_xml(item: &ManifestItem) -> bool {
+    item.media_type == "application/xhtml+xml"
+        || item.media_type == "application/x-dtbncx+xml"
+        || item.media_type == "image/svg+xml"
+}
+
+fn check_well_formed(content: &str) -> Result<()> {
+    let mut reader = Reader::from_str(content);
+    let mut depth = 0usize;
+    let mut has_root = false;
+    loop {
+        match reader.read_event()? {
+            Event::Start(_) => {
+                depth += 1;
+                has_root = true;
+            }
+            Event::End(_) => depth -= 1,
+            Event::Empty(_) => has_root = true,
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    match (has_root, depth) {
+        (false, _) => Err(anyhow::anyhow!("没有根元素")),
+        (true, 0) => Ok(()),
+        (true, _) => Err(anyhow::anyhow!("存在未闭合的元素")),
+    }
+}
+
+fn local_name(element: &BytesStart) -> String {
+    String::from_utf8_lossy(element.local_name().as_ref()).into_owned()
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Result<Option<String>> {
+    for attr in element.attributes() {
+        let attr = attr?;
+        if attr.key.local_name().as_ref() == name.as_bytes() {
+            return Ok(Some(attr.unescape_value()?.into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+fn attribute_values(content: &str, element: &str, name: &str) -> Result<Vec<String>> {
+    let mut reader = Reader::from_str(content);
+    let mut values = vec![];
+    loop {
+        match reader.read_event()? {
+            Event::Start(start) | Event::Empty(start) if local_name(&start) == element => {
+                values.extend(attribute(&start, name)?);
+            }
+            Event::Eof => return Ok(values),
+            _ => {}
+        }
+    }
+}
+
+type Package = (String, Vec<ManifestItem>, Vec<String>, Option<String>);
+
+/// 读取包文档的版本、manifest、spine 与 toc
+fn parse_opf(content: &str, opf_path: &str) -> Result<Package> {
+    let base = opf_path.rsplit_once('/').map_or("", |(dir, _)| dir);
+    let mut reader = Reader::from_str(content);
+    let mut version = String::new();
+    let mut items = vec![];
+    let mut spine = vec![];
+    let mut toc = None;
+    loop {
+        match reader.read_event()? {
+            Event::Start(start) | Event::Empty(start) => match local_name(&start).as_str() {
+                "package" => version = attribute(&start, "version")?.unwrap_or_default(),
+                "item" => items.push(ManifestItem {
+                    id: attribute(&start, "id")?.unwrap_or_default(),
+                    path: resolve(base, &attribute(&start, "href")?.unwrap_or_default()),
+                    media_type: attribute(&start, "media-type")?.unwrap_or_default(),
+                    properties: attribute(&start, "properties")?.unwrap_or_default(),
+                }),
+                "itemref" => spine.extend(attribute(&start, "idref")?),
+                "spine" => toc = attribute(&start, "toc")?,
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    check_well_formed(content)?;
+    Ok((version, items, spine, toc))
+}
+
+/// 将相对于包文档的 href 解析为压缩包内的路径
+fn resolve(base: &str, href: &str) -> String {
+    let href = href.split(['#', '?']).next().unwrap_or_default();
+    let mut segments: Vec<String> = base
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(str::to_string)
+        .collect();
+    for segment in href.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(percent_decode(segment)),
+        }
+    }
+    segments.join("/")
+}
+
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let hex = bytes
+            .get(idx + 1..idx + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[idx], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                idx += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                idx += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    use super::*;
+
+    const CONTAINER: &str = r#"<?xml version="1.0"?><container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container"><rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles></container>"#;
+
+    fn opf(items: &str, spine: &str) -> String {
+        format!(
+            r#"<?xml version="1.0"?><package xmlns="http://www.idpf.org/2007/opf" version="2.0" unique-identifier="uuid_id"><metadata/><manifest>{}</manifest><spine toc="ncx">{}</spine></package>"#,
+            items, spine
+        )
+    }
+
+    fn epub(files: &[(&str, &str, CompressionMethod)]) -> Result<Cursor<Vec<u8>>> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for (name, content, method) in files {
+            writer.start_file(
+                *name,
+                SimpleFileOptions::default().compression_method(*method),
+            )?;
+            writer.write_all(content.as_bytes())?;
+        }
+        let mut cursor = writer.finish()?;
+        cursor.set_position(0);
+        Ok(cursor)
+    }
+
+    #[test]
+    fn test_valid() -> Result<()> {
+        let opf = opf(
+            r#"<item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/><item id="c1" href="Text/%E7%AC%AC1.xhtml" media-type="application/xhtml+xml"/>"#,
+            r#"<itemref idref="c1"/>"#,
+        );
+        let report = validate_archive(epub(&[
+            (
+                "mimetype",
+                "application/epub+zip",
+                CompressionMethod::Stored,
+            ),
+            (
+                "META-INF/container.xml",
+                CONTAINER,
+                CompressionMethod::Deflated,
+            ),
+            ("OEBPS/content.opf", &opf, CompressionMethod::Deflated),
+            ("OEBPS/toc.ncx", "<ncx/>", CompressionMethod::Deflated),
+            (
+                "OEBPS/Text/第1.xhtml",
+                "<html><body><p>正文</p></body></html>",
+                CompressionMethod::Deflated,
+            ),
+        ])?)?;
+        assert!(report.is_valid(), "{:?}", report);
+        assert!(report.warnings.is_empty(), "{:?}", report);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid() -> Result<()> {
+        let opf = opf(
+            r#"<item id="c1" href="Text/1.xhtml" media-type="application/xhtml+xml"/><item id="c2" href="Text/2.xhtml" media-type="application/xhtml+xml"/>"#,
+            r#"<itemref idref="c1"/><itemref idref="c3"/>"#,
+        );
+        let report = validate_archive(epub(&[
+            (
+                "mimetype",
+                "application/epub+zip",
+                CompressionMethod::Deflated,
+            ),
+            (
+                "META-INF/container.xml",
+                CONTAINER,
+                CompressionMethod::Deflated,
+            ),
+            ("OEBPS/content.opf", &opf, CompressionMethod::Deflated),
+            (
+                "OEBPS/Text/1.xhtml",
+                "<html><body><p>正文</body></html>",
+                CompressionMethod::Deflated,
+            ),
+            (
+                "OEBPS/Text/2.xhtml",
+                r#"<html><body><img src="https://mock.test/a.jpg" alt=""/><img src="../Images/orphan.jpg" alt=""/></body></html>"#,
+                CompressionMethod::Deflated,
+            ),
+            ("OEBPS/Images/orphan.jpg", "", CompressionMethod::Deflated),
+        ])?)?;
+        assert!(!report.is_valid());
+        let errors = report.errors.join("\n");
+        assert!(errors.contains("mimetype 不应压缩"), "{}", errors);
+        assert!(errors.contains("c3"), "{}", errors);
+        assert!(errors.contains("ncx"), "{}", errors);
+        assert!(
+            errors.contains("OEBPS/Text/1.xhtml 不是格式正确的 XML"),
+            "{}",
+            errors
+        );
+        for src in ["https://mock.test/a.jpg", "../Images/orphan.jpg"] {
+            assert!(
+                errors.contains(&format!(
+                    "OEBPS/Text/2.xhtml 中的图片 {} 不在 manifest 中",
+                    src
+                )),
+                "{}",
+                errors
+            );
+        }
+        assert_eq!(report.errors.len(), 6, "{}", errors);
+        assert_eq!(
+            report.warnings,
+            vec!["OEBPS/Images/orphan.jpg 未在 manifest 中声明".to_string()]
+        );
+
+        let report = validate_archive(epub(&[(
+            "META-INF/container.xml",
+            CONTAINER,
+            CompressionMethod::Deflated,
+        )])?)?;
+        assert!(report.errors[0].contains("第一个文件应为 mimetype"));
+        assert!(report.errors[1].contains("OEBPS/content.opf 不存在"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_ids() -> Result<()> {
+        let opf = opf(
+            r#"<item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/><item id="1.xhtml" href="Text/1.xhtml" media-type="application/xhtml+xml"/>"#,
+            r#"<itemref idref="1.xhtml"/>"#,
+        );
+        let report = validate_archive(epub(&[
+            (
+                "mimetype",
+                "application/epub+zip",
+                CompressionMethod::Stored,
+            ),
+            (
+                "META-INF/container.xml",
+                CONTAINER,
+                CompressionMethod::Deflated,
+            ),
+            ("OEBPS/content.opf", &opf, CompressionMethod::Deflated),
+            ("OEBPS/toc.ncx", "<ncx/>", CompressionMethod::Deflated),
+            (
+                "OEBPS/Text/1.xhtml",
+                "<html><body><p>正文</p></body></html>",
+                CompressionMethod::Deflated,
+            ),
+        ])?)?;
+        assert_eq!(
+            report.errors,
+            vec!["manifest 中的 id 1.xhtml 不是合法的 XML ID".to_string()]
+        );
+
+        for id in ["ep1", "cover.jpg", "_a-b", "封面"] {
+            assert!(is_xml_id(id), "{}", id);
+        }
+        for id in ["", "1.xhtml", "-a", ".a", "a:b", "a b"] {
+            assert!(!is_xml_id(id), "{}", id);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve("OEBPS", "Text/1.xhtml#top"), "OEBPS/Text/1.xhtml");
+        assert_eq!(
+            resolve("OEBPS/Text", "../Images/a%20b.jpg"),
+            "OEBPS/Images/a b.jpg"
+        );
+        assert_eq!(resolve("", "content.xhtml"), "content.xhtml");
+    }
+}